@@ -1,9 +1,12 @@
 use clap::{Parser, Subcommand};
 use glob::Pattern;
-use serde::Deserialize;
-use std::io::{self, Write};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::{self, exit};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 // =============================================================================
 // CLI
@@ -27,41 +30,148 @@ enum Command {
     Init,
     /// List protected files and their current state
     Status,
-    /// Make all protected files read-only
-    Lock,
-    /// Restore write permissions (must run from outside target directory)
+    /// Enable protection and make all protected files read-only
+    Lock {
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Disable protection and restore write permissions (must run from outside target directory)
     Unlock {
         /// Path to the directory containing .donttouch.toml
         target: String,
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Check if any protected files are writable (exits non-zero if so)
     Check,
-    /// Check if protection is enabled before push (used by pre-push hook)
+    /// Check protection before push: enabled flag and pushed commits (used by pre-push hook)
     #[command(name = "check-push")]
-    CheckPush,
-    /// Disable protection (must run from outside target directory)
+    CheckPush {
+        /// Remote name (passed by git)
+        remote: Option<String>,
+        /// Remote URL (passed by git)
+        url: Option<String>,
+    },
+    /// Check what a merge, rebase or rewritten commit changes (used by git hooks)
+    #[command(name = "check-hook")]
+    CheckHook {
+        /// Hook name: commit-msg, pre-merge-commit, pre-rebase or post-rewrite
+        hook: String,
+        /// Arguments git passed to the hook
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Check a commit range for unapproved changes to protected files (for CI)
+    Ci {
+        /// Base of the range, e.g. origin/main
+        #[arg(long)]
+        base: String,
+        /// Tip of the range
+        #[arg(long, default_value = "HEAD")]
+        head: String,
+    },
+    /// Deprecated alias for 'unlock'
+    #[command(hide = true)]
     Disable {
         /// Path to the directory containing .donttouch.toml
         target: String,
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Deprecated alias for 'lock'
+    #[command(hide = true)]
+    Enable {
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
     },
-    /// Re-enable protection (lock files, resume checks)
-    Enable,
     /// Remove donttouch from a directory (must run from outside target directory)
     Remove {
         /// Path to the directory containing .donttouch.toml
         target: String,
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Show which pattern protects a given file
     Why {
         /// File path to check
         file: String,
     },
+    /// Diagnose config, hook and agent instruction problems (exits non-zero on errors)
+    Doctor,
+    /// Generate files from the protected patterns
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+    /// Install, upgrade or remove donttouch's git hooks
+    Hooks {
+        #[command(subcommand)]
+        action: HooksAction,
+    },
     /// Add agent instructions to coding agent config files
     Inject {
         /// Preview changes without writing
         #[arg(long)]
         dry_run: bool,
+        /// Only re-render existing instruction blocks
+        #[arg(long)]
+        update: bool,
+    },
+    /// Reinstate a setup saved by 'remove' (must run from outside target directory)
+    Restore {
+        /// Path to the directory donttouch was removed from
+        target: String,
+        /// Backup file written by 'remove'
+        backup: String,
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExportTarget {
+    /// Write the protected patterns into a managed block in CODEOWNERS
+    Codeowners {
+        /// Owner to require review from, e.g. @org/team (repeatable; patterns with their own `owner` use that)
+        #[arg(long)]
+        owner: Vec<String>,
+        /// CODEOWNERS file to write (default: the existing one, else .github/CODEOWNERS)
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write patterns marked `hide = true` into agent ignore files (.cursorignore, ...)
+    Ignore {
+        /// Export every protected pattern, not just hidden ones
+        #[arg(long)]
+        all: bool,
+        /// Preview changes without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HooksAction {
+    /// Install missing hooks (reports outdated ones without changing them)
+    Install,
+    /// Install missing hooks and rewrite outdated donttouch blocks in place
+    Upgrade,
+    /// Remove donttouch from all hooks (must run from outside target directory)
+    Uninstall {
+        /// Path to the directory containing .donttouch.toml
+        target: String,
     },
+    /// Show which hooks are installed and whether they are up to date
+    Status,
 }
 
 // =============================================================================
@@ -71,19 +181,262 @@ enum Command {
 #[derive(Deserialize)]
 struct ConfigFile {
     protect: ProtectSection,
+    #[serde(default)]
+    discover: DiscoverSection,
+    #[serde(default)]
+    approval: ApprovalSection,
+    #[serde(default)]
+    inject: InjectSection,
 }
 
 #[derive(Deserialize)]
 struct ProtectSection {
-    patterns: Vec<String>,
+    #[serde(rename = "patterns")]
+    entries: Vec<PatternEntry>,
     #[serde(default = "default_enabled")]
     enabled: bool,
+    /// What installed hooks do when the donttouch binary can't be found
+    #[serde(default)]
+    hook_policy: HookPolicy,
+}
+
+/// A `[protect] patterns` entry: a bare glob, or a table with options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PatternEntry {
+    Glob(String),
+    Table {
+        pattern: String,
+        /// Keep matching files out of agents' view too (see `export ignore`)
+        #[serde(default)]
+        hide: bool,
+        /// Why the files are protected, shown when a change is blocked
+        reason: Option<String>,
+        /// Who to ask about changes (also their CODEOWNERS owner)
+        owner: Option<String>,
+    },
+}
+
+impl PatternEntry {
+    fn pattern(&self) -> &str {
+        match self {
+            PatternEntry::Glob(p) | PatternEntry::Table { pattern: p, .. } => p,
+        }
+    }
+
+    fn hide(&self) -> bool {
+        matches!(self, PatternEntry::Table { hide: true, .. })
+    }
+
+    fn owner(&self) -> Option<&str> {
+        match self {
+            PatternEntry::Table { owner, .. } => owner.as_deref(),
+            PatternEntry::Glob(_) => None,
+        }
+    }
+
+    /// "reason (owner: @team)", or None when neither is set.
+    fn note(&self) -> Option<String> {
+        let PatternEntry::Table { reason, owner, .. } = self else {
+            return None;
+        };
+        match (reason, owner) {
+            (Some(r), Some(o)) => Some(format!("{r} (owner: {o})")),
+            (Some(r), None) => Some(r.clone()),
+            (None, Some(o)) => Some(format!("owner: {o}")),
+            (None, None) => None,
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Patterns to lock and check: `[protect] patterns`, plus the agent files
+    /// carrying instructions when `[inject] protect` is set.
+    fn protected_patterns(&self, root: &Path) -> Vec<String> {
+        let mut patterns = self.protect.patterns();
+        if self.inject.protect {
+            patterns.extend(
+                instructed_agent_files(root, self)
+                    .iter()
+                    .map(|p| Pattern::escape(p)),
+            );
+        }
+        patterns
+    }
+}
+
+impl ProtectSection {
+    /// The glob of every entry, in config order.
+    fn patterns(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|e| e.pattern().to_string())
+            .collect()
+    }
+
+    /// Note of the first annotated entry matching any of `paths` (root-relative).
+    fn note_for(&self, paths: &[&str]) -> Option<String> {
+        self.entries.iter().find_map(|e| {
+            let pattern = Pattern::new(e.pattern()).ok()?;
+            paths
+                .iter()
+                .any(|p| pattern.matches(p))
+                .then(|| e.note())
+                .flatten()
+        })
+    }
+
+    /// A violation line: "   • <item> — <note>" when the path has a note.
+    fn bullet(&self, indent: &str, item: impl std::fmt::Display, paths: &[&str]) -> String {
+        match self.note_for(paths) {
+            Some(note) => format!("{indent}• {item} — {note}"),
+            None => format!("{indent}• {item}"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+enum HookPolicy {
+    /// Block the commit or push
+    #[default]
+    FailClosed,
+    /// Print a warning and let it through
+    Warn,
+    /// Let it through silently
+    Skip,
+}
+
+impl HookPolicy {
+    fn name(&self) -> &'static str {
+        match self {
+            HookPolicy::FailClosed => "fail-closed",
+            HookPolicy::Warn => "warn",
+            HookPolicy::Skip => "skip",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [HookPolicy::FailClosed, HookPolicy::Warn, HookPolicy::Skip]
+            .into_iter()
+            .find(|p| p.name() == name)
+    }
 }
 
 fn default_enabled() -> bool {
     true
 }
 
+/// Lets commits change protected files when their message carries an approval
+/// trailer and, optionally, they are signed by an allowed key.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ApprovalSection {
+    /// Trailer key, e.g. "Donttouch-Approved-By". Unset means no commit is approved.
+    trailer: Option<String>,
+    /// GPG/SSH key fingerprints allowed to sign approved commits (empty = no signature needed)
+    signers: Vec<String>,
+}
+
+impl ApprovalSection {
+    /// Reject `signers` entries too short to name a single key.
+    fn validate(&self) -> Result<(), String> {
+        for signer in &self.signers {
+            if signer.starts_with("SHA256:") {
+                continue;
+            }
+            let hex = signer.replace(' ', "");
+            if hex.len() < 16 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "[approval] signers entry '{signer}' is not a full fingerprint, a 16-digit \
+                     long key ID or an SSH 'SHA256:' fingerprint"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Agent files `inject` writes to, beyond the built-in ones, and how strictly
+/// their instructions are guarded.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct InjectSection {
+    targets: Vec<InjectTarget>,
+    /// `check` fails when an agent file's instructions are missing or outdated
+    enforce: bool,
+    /// Lock agent files that carry instructions along with the protected files
+    protect: bool,
+}
+
+/// A user-defined `[[inject.targets]]` entry.
+#[derive(Deserialize)]
+#[serde(tag = "format", rename_all = "kebab-case")]
+enum InjectTarget {
+    /// Append the block to a markdown file
+    Markdown {
+        path: String,
+        #[serde(default)]
+        create: bool,
+        /// Also files of this name in subdirectories
+        #[serde(default)]
+        nested: bool,
+    },
+    /// A file donttouch owns, with optional frontmatter
+    Rule {
+        path: String,
+        #[serde(default)]
+        frontmatter: String,
+    },
+    /// An item in a YAML list
+    Yaml {
+        path: String,
+        key: String,
+        #[serde(default)]
+        create: bool,
+    },
+    /// A string in a JSON array
+    Json {
+        path: String,
+        key: String,
+        #[serde(default)]
+        create: bool,
+    },
+}
+
+/// Controls which files are considered during discovery.
+#[derive(Deserialize)]
+#[serde(default)]
+struct DiscoverSection {
+    /// Directories never descended into. Entries without a `/` match a directory
+    /// name anywhere in the tree; entries with a `/` match a root-relative path.
+    skip: Vec<String>,
+    /// Skip files ignored by `.gitignore` / `.ignore` files
+    gitignore: bool,
+    /// Only consider files tracked by git (`git ls-files`)
+    tracked_only: bool,
+    /// Reuse the last discovery result while no directory has changed
+    cache: bool,
+    /// Worker threads for the directory walk (0 = one per CPU)
+    threads: usize,
+    /// Lock the target of a protected symlink when it is inside the project.
+    /// Targets outside the project are never touched.
+    protect_link_targets: bool,
+}
+
+impl Default for DiscoverSection {
+    fn default() -> Self {
+        DiscoverSection {
+            skip: vec!["target".into(), "node_modules".into()],
+            gitignore: false,
+            tracked_only: false,
+            cache: false,
+            threads: 0,
+            protect_link_targets: true,
+        }
+    }
+}
+
 // =============================================================================
 // Context — describes the environment donttouch is running in
 // =============================================================================
@@ -94,7 +447,9 @@ enum Context {
     Plain,
     /// Git repository
     Git {
-        has_husky: bool,
+        manager: HookManager,
+        /// Where hook files are installed (Husky, core.hooksPath or the git dir's hooks/)
+        hooks_dir: PathBuf,
         hooks_installed: bool,
     },
 }
@@ -106,22 +461,22 @@ impl Context {
             return Context::Plain;
         }
 
+        // `.git` is a directory in a normal clone and a file in worktrees and submodules
         let git_dir = root.join(".git");
         if !git_dir.exists() {
             return Context::Plain;
         }
 
-        let has_husky = root.join(".husky").is_dir();
+        let manager = HookManager::detect(root);
+        let hooks_dir = resolve_hooks_dir(root, manager == HookManager::Husky);
 
-        // Check if donttouch hooks are installed
-        let hooks_installed = if has_husky {
-            hook_contains(&root.join(".husky/pre-commit"), "donttouch")
-        } else {
-            hook_contains(&root.join(".git/hooks/pre-commit"), "donttouch")
-        };
+        // The policy only decides current vs outdated, not whether hooks are there
+        let hooks_installed =
+            hooks_state(root, manager, &hooks_dir, HookPolicy::default()) != HookState::Missing;
 
         Context::Git {
-            has_husky,
+            manager,
+            hooks_dir,
             hooks_installed,
         }
     }
@@ -170,6 +525,9 @@ enum State {
     /// Terminal: output an error and exit with failure
     Error { message: String },
 
+    /// Terminal: output a report whose findings are only warnings, and exit 2
+    Warning { message: String },
+
     /// Terminal: program ends
     End { code: i32 },
 }
@@ -178,6 +536,16 @@ enum State {
 struct ProtectedFile {
     path: PathBuf,
     readonly: bool,
+    /// Set when the matched path is a symlink
+    link: Option<Symlink>,
+}
+
+/// Where a protected symlink points and whether its target may be chmodded.
+struct Symlink {
+    /// Canonical target, or None if the link is dangling
+    target: Option<PathBuf>,
+    /// Why permissions are not changed through this link
+    blocked: Option<&'static str>,
 }
 
 impl State {
@@ -203,6 +571,10 @@ impl State {
                     eprintln!("{message}");
                     State::End { code: 1 }
                 }
+                State::Warning { message } => {
+                    println!("{message}");
+                    State::End { code: 2 }
+                }
                 State::End { code } => exit(code),
             };
         }
@@ -215,8 +587,12 @@ impl State {
 
 /// Start state: inspect filesystem + command to determine next state.
 fn handle_start(command: Command, ignoregit: bool) -> State {
+    let command = resolve_deprecated(command);
     match command {
         Command::Init => {
+            if let Err(e) = acquire_process_lock(Path::new(".")) {
+                return State::Error { message: e };
+            }
             if Path::new(".donttouch.toml").exists() {
                 State::Error {
                     message: "⚠️  .donttouch.toml already exists. Nothing to do.".into(),
@@ -227,47 +603,125 @@ fn handle_start(command: Command, ignoregit: bool) -> State {
             }
         }
 
+        // Restore runs where the config no longer exists
+        Command::Restore {
+            target,
+            backup,
+            dry_run,
+        } => do_restore(&target, &backup, dry_run),
+
         // All other commands require an existing config
         cmd => {
             let root = match &cmd {
-                Command::Disable { target }
-                | Command::Unlock { target }
-                | Command::Remove { target } => match assert_outside(target) {
+                Command::Unlock { target, .. }
+                | Command::Remove { target, .. }
+                | Command::Hooks {
+                    action: HooksAction::Uninstall { target },
+                } => match assert_outside(target) {
                     Ok(p) => p,
                     Err(e) => return State::Error { message: e },
                 },
                 _ => PathBuf::from("."),
             };
+            run_configured(cmd, root, ignoregit)
+        }
+    }
+}
 
-            let config_path = root.join(".donttouch.toml");
-            let content = match std::fs::read_to_string(&config_path) {
-                Ok(c) => c,
-                Err(_) => {
-                    return State::Error {
-                        message: "No .donttouch.toml found. Run 'donttouch init' first.".into(),
-                    }
-                }
-            };
-
-            let config: ConfigFile = match toml::from_str(&content) {
-                Ok(c) => c,
-                Err(e) => {
-                    return State::Error {
-                        message: format!("Invalid {}: {e}", config_path.display()),
-                    }
-                }
-            };
+/// Run a command that needs the config in `root`: load it and the protected
+/// files under the process lock, then dispatch.
+fn run_configured(cmd: Command, root: PathBuf, ignoregit: bool) -> State {
+    // Take the lock before reading anything, so the config and file states
+    // below are a snapshot no other donttouch is changing. Read-only commands
+    // share it, waiting out a running lock or unlock.
+    let _shared = if cmd.mutates() {
+        if let Err(e) = acquire_process_lock(&root) {
+            return State::Error { message: e };
+        }
+        None
+    } else {
+        match share_process_lock(&root) {
+            Ok(file) => file,
+            Err(e) => return State::Error { message: e },
+        }
+    };
 
-            let context = Context::detect(&root, ignoregit);
-            let patterns = compile_patterns(&config.protect.patterns);
-            let files = discover_files(&root, &patterns);
+    let config_path = root.join(".donttouch.toml");
+    let content = match std::fs::read_to_string(&config_path) {
+        Ok(c) => c,
+        Err(_) => {
+            return State::Error {
+                message: "No .donttouch.toml found. Run 'donttouch init' first.".into(),
+            }
+        }
+    };
 
-            if config.protect.enabled {
-                dispatch_enabled(cmd, config, files, root, context)
-            } else {
-                dispatch_disabled(cmd, config, files, root, context)
+    let config: ConfigFile = match toml::from_str(&content) {
+        Ok(c) => c,
+        Err(e) => {
+            return State::Error {
+                message: format!("Invalid {}: {e}", config_path.display()),
             }
         }
+    };
+    if let Err(e) = config.approval.validate() {
+        return State::Error {
+            message: format!("Invalid {}: {e}", config_path.display()),
+        };
+    }
+
+    let context = Context::detect(&root, ignoregit);
+    let patterns = compile_patterns(&config.protected_patterns(&root));
+    let files = discover_files(&root, &patterns, &config.discover);
+
+    if config.protect.enabled {
+        dispatch_enabled(cmd, config, files, root, context)
+    } else {
+        dispatch_disabled(cmd, config, files, root, context)
+    }
+}
+
+impl Command {
+    /// Whether the command changes files, and so must hold the process lock
+    /// rather than share it.
+    fn mutates(&self) -> bool {
+        match self {
+            Command::Init => true,
+            Command::Lock { dry_run }
+            | Command::Unlock { dry_run, .. }
+            | Command::Enable { dry_run }
+            | Command::Disable { dry_run, .. }
+            | Command::Remove { dry_run, .. }
+            | Command::Restore { dry_run, .. }
+            | Command::Inject { dry_run, .. } => !dry_run,
+            Command::Export {
+                target:
+                    ExportTarget::Codeowners { dry_run, .. } | ExportTarget::Ignore { dry_run, .. },
+            } => !dry_run,
+            Command::Hooks { action } => !matches!(action, HooksAction::Status),
+            Command::Status
+            | Command::Check
+            | Command::CheckPush { .. }
+            | Command::CheckHook { .. }
+            | Command::Ci { .. }
+            | Command::Why { .. }
+            | Command::Doctor => false,
+        }
+    }
+}
+
+/// Map the deprecated `enable`/`disable` onto `lock`/`unlock`, with a warning.
+fn resolve_deprecated(command: Command) -> Command {
+    match command {
+        Command::Enable { dry_run } => {
+            eprintln!("⚠️  'donttouch enable' is deprecated; use 'donttouch lock'.");
+            Command::Lock { dry_run }
+        }
+        Command::Disable { target, dry_run } => {
+            eprintln!("⚠️  'donttouch disable' is deprecated; use 'donttouch unlock {target}'.");
+            Command::Unlock { target, dry_run }
+        }
+        cmd => cmd,
     }
 }
 
@@ -280,19 +734,25 @@ fn dispatch_enabled(
     context: Context,
 ) -> State {
     match cmd {
-        Command::Status => do_status(&config, &files, true, &context),
-        Command::Lock => do_lock(&files),
-        Command::Unlock { .. } => do_unlock(&files, &root),
-        Command::Check => do_check(&files, &root, &context),
-        Command::CheckPush => do_check_push(true, &context),
-        Command::Enable => State::Done {
-            message: "✅ Protection is already enabled.".into(),
-        },
-        Command::Disable { .. } => do_disable(&files, &root),
-        Command::Remove { .. } => do_remove(&files, &root, &context),
-        Command::Inject { dry_run } => do_inject(&root, dry_run),
+        Command::Status => do_status(&config, &files, true, &root, &context),
+        Command::Lock { dry_run } => do_lock(&files, &config, &root, true, dry_run),
+        Command::Unlock { dry_run, .. } => do_unlock(&files, &root, true, dry_run),
+        Command::Check => do_check(&files, &config, &root, &context),
+        Command::CheckPush { .. } => do_check_push(true, &config, &root, &context),
+        Command::CheckHook { ref hook, ref args } => {
+            do_check_hook(hook, args, &config, &root, &context)
+        }
+        Command::Ci { ref base, ref head } => do_ci(true, base, head, &config, &root, &context),
+        Command::Remove { dry_run, .. } => do_remove(&files, &config, &root, &context, dry_run),
+        Command::Inject { dry_run, update } => do_inject(&config, &root, dry_run, update),
         Command::Why { ref file } => do_why(file, &config),
-        Command::Init => unreachable!(),
+        Command::Doctor => do_doctor(&config, &root, &context),
+        Command::Export { ref target } => do_export(target, &config, &root),
+        Command::Hooks { ref action } => do_hooks(action, &config, &root, &context),
+        Command::Init
+        | Command::Restore { .. }
+        | Command::Enable { .. }
+        | Command::Disable { .. } => unreachable!(),
     }
 }
 
@@ -305,23 +765,27 @@ fn dispatch_disabled(
     context: Context,
 ) -> State {
     match cmd {
-        Command::Status => do_status(&config, &files, false, &context),
-        Command::Lock => State::Error {
-            message: "⏸️  Protection is disabled. Run 'donttouch enable' first.".into(),
-        },
-        Command::Unlock { .. } => do_unlock(&files, &root),
+        Command::Status => do_status(&config, &files, false, &root, &context),
+        Command::Lock { dry_run } => do_lock(&files, &config, &root, false, dry_run),
+        Command::Unlock { dry_run, .. } => do_unlock(&files, &root, false, dry_run),
         Command::Check => State::Done {
             message: "⏸️  Protection is disabled. Skipping check.".into(),
         },
-        Command::CheckPush => do_check_push(false, &context),
-        Command::Enable => do_enable(&files, &root),
-        Command::Disable { .. } => State::Done {
-            message: "⏸️  Protection is already disabled.".into(),
+        Command::CheckPush { .. } => do_check_push(false, &config, &root, &context),
+        Command::CheckHook { .. } => State::Done {
+            message: "⏸️  Protection is disabled. Skipping check.".into(),
         },
-        Command::Remove { .. } => do_remove(&files, &root, &context),
-        Command::Inject { dry_run } => do_inject(&root, dry_run),
+        Command::Ci { ref base, ref head } => do_ci(false, base, head, &config, &root, &context),
+        Command::Remove { dry_run, .. } => do_remove(&files, &config, &root, &context, dry_run),
+        Command::Inject { dry_run, update } => do_inject(&config, &root, dry_run, update),
         Command::Why { ref file } => do_why(file, &config),
-        Command::Init => unreachable!(),
+        Command::Doctor => do_doctor(&config, &root, &context),
+        Command::Export { ref target } => do_export(target, &config, &root),
+        Command::Hooks { ref action } => do_hooks(action, &config, &root, &context),
+        Command::Init
+        | Command::Restore { .. }
+        | Command::Enable { .. }
+        | Command::Disable { .. } => unreachable!(),
     }
 }
 
@@ -346,14 +810,23 @@ patterns = []
     }
 }
 
-/// Initializing: prompt user for patterns
+/// Initializing: offer suggested patterns, then prompt user for more
 fn handle_initializing(config_path: &Path, context: Context) -> State {
     println!("✅ Created .donttouch.toml\n");
+
+    let mut patterns: Vec<String> = match offer_suggestions(Path::new(".")) {
+        Ok(p) => p,
+        Err(e) => {
+            return State::Error {
+                message: format!("Failed to read input: {e}"),
+            };
+        }
+    };
+
     println!("Add file patterns to protect (glob syntax, one per line).");
     println!("Examples: .env, secrets/**, docker-compose.prod.yml");
     println!("Press Enter on an empty line when done.\n");
 
-    let mut patterns: Vec<String> = Vec::new();
     let stdin = io::stdin();
 
     loop {
@@ -369,6 +842,9 @@ fn handle_initializing(config_path: &Path, context: Context) -> State {
                     break;
                 }
                 match Pattern::new(&trimmed) {
+                    Ok(_) if patterns.contains(&trimmed) => {
+                        println!("   ✅ Already added: {trimmed}");
+                    }
                     Ok(_) => {
                         println!("   ✅ Added: {trimmed}");
                         patterns.push(trimmed);
@@ -407,7 +883,7 @@ patterns = [
 "#
         );
 
-        if let Err(e) = std::fs::write(config_path, config) {
+        if let Err(e) = write_atomic(config_path, config.as_bytes()) {
             return State::Error {
                 message: format!("Failed to write config: {e}"),
             };
@@ -451,8 +927,8 @@ fn handle_end_init(context: Context) -> State {
             }
         };
 
-        let patterns = compile_patterns(&config.protect.patterns);
-        let files = discover_files(Path::new("."), &patterns);
+        let patterns = compile_patterns(&config.protect.patterns());
+        let files = discover_files(Path::new("."), &patterns, &config.discover);
 
         if files.is_empty() {
             println!(
@@ -460,21 +936,25 @@ fn handle_end_init(context: Context) -> State {
             );
         } else {
             // Lock the files inline (don't return to state machine — we need to continue to hooks)
-            let mut locked = 0;
+            let mut plan = Plan::default();
             for f in &files {
-                if !f.readonly && set_file_readonly(&f.path, true).is_ok() {
-                    println!("   🔒 {}", f.path.display());
-                    locked += 1;
+                if !f.readonly {
+                    if let Err(e) = plan.set_protected(f, true) {
+                        println!("   ❌ {e}");
+                    }
                 }
             }
             // Lock config too
-            let config_path = Path::new(".donttouch.toml");
-            if !is_file_readonly(config_path) && set_file_readonly(config_path, true).is_ok() {
-                println!("   🔒 .donttouch.toml");
-                locked += 1;
+            plan.set_readonly(Path::new(".donttouch.toml"), true);
+            let applied = plan.apply(Path::new("."));
+            print!("{}", applied.render(&plan.notes));
+            if !applied.ok() {
+                return State::Error {
+                    message: "Fix the error above and run 'donttouch lock'.".into(),
+                };
             }
-            if locked > 0 {
-                println!("\n✅ Locked {locked} file(s).");
+            if applied.modes > 0 {
+                println!("\n✅ Locked {} file(s).", applied.modes);
             }
         }
     } else {
@@ -493,11 +973,12 @@ fn handle_end_init(context: Context) -> State {
 
 /// OfferHooks: ask user if they want to install git hooks
 fn handle_offer_hooks(context: Context) -> State {
-    let (has_husky, hooks_installed) = match &context {
+    let (manager, hooks_dir, hooks_installed) = match &context {
         Context::Git {
-            has_husky,
+            manager,
+            hooks_dir,
             hooks_installed,
-        } => (*has_husky, *hooks_installed),
+        } => (*manager, hooks_dir, *hooks_installed),
         Context::Plain => {
             return State::Done {
                 message: String::new(),
@@ -514,11 +995,19 @@ fn handle_offer_hooks(context: Context) -> State {
         return next;
     }
 
-    if has_husky {
-        println!("\n🐶 Husky detected.");
-        print!("Install donttouch hooks into Husky? [Y/n] ");
-    } else {
-        print!("\nInstall git hooks (pre-commit + pre-push)? [Y/n] ");
+    match manager {
+        HookManager::GitHooks => {
+            print!("\nInstall git hooks (commit, merge, rebase and push checks)? [Y/n] ")
+        }
+        HookManager::Husky => {
+            println!("\n🐶 Husky detected.");
+            print!("Install donttouch hooks into Husky? [Y/n] ");
+        }
+        _ => {
+            let config = manager.config_file(Path::new(".")).unwrap_or_default();
+            println!("\n🪝 {} detected.", manager.name());
+            print!("Add donttouch hooks to {}? [Y/n] ", config.display());
+        }
     }
     io::stdout().flush().ok();
 
@@ -527,920 +1016,7117 @@ fn handle_offer_hooks(context: Context) -> State {
     let answer = answer.trim().to_lowercase();
 
     if answer.is_empty() || answer == "y" || answer == "yes" {
-        if has_husky {
-            install_husky_hooks();
-        } else {
-            install_git_hooks();
+        match install_hooks(
+            Path::new("."),
+            manager,
+            hooks_dir,
+            false,
+            HookPolicy::default(),
+        ) {
+            Ok(()) => println!("✅ Git hooks installed."),
+            Err(e) => {
+                println!("❌ {e}");
+                println!("Fix it, then run 'donttouch hooks install'.");
+            }
         }
-        println!("✅ Git hooks installed.");
         next
     } else {
-        println!("Ok. Run 'donttouch init' in a git repo to install hooks later.");
+        println!("Ok. Run 'donttouch hooks install' to install hooks later.");
         next
     }
 }
 
 // =============================================================================
-// Git Hook Installation
+// Pattern Suggestions
 // =============================================================================
 
-fn install_git_hooks() {
-    std::fs::create_dir_all(".git/hooks").ok();
-    install_hook_file(
-        Path::new(".git/hooks/pre-commit"),
-        "donttouch check",
-        "pre-commit",
-    );
-    install_hook_file(
-        Path::new(".git/hooks/pre-push"),
-        "donttouch check-push",
-        "pre-push",
-    );
+/// A pattern offered during init when it matches files in the project.
+struct Suggestion {
+    pattern: &'static str,
+    description: &'static str,
 }
 
-fn install_husky_hooks() {
-    install_hook_file(
-        Path::new(".husky/pre-commit"),
-        "donttouch check",
-        "pre-commit",
-    );
-    install_hook_file(
-        Path::new(".husky/pre-push"),
-        "donttouch check-push",
-        "pre-push",
-    );
-}
+const SUGGESTIONS: &[Suggestion] = &[
+    Suggestion {
+        pattern: ".env",
+        description: "environment secrets",
+    },
+    Suggestion {
+        pattern: ".env.*",
+        description: "environment variants",
+    },
+    Suggestion {
+        pattern: "Cargo.lock",
+        description: "Rust lockfile",
+    },
+    Suggestion {
+        pattern: "package-lock.json",
+        description: "npm lockfile",
+    },
+    Suggestion {
+        pattern: "yarn.lock",
+        description: "Yarn lockfile",
+    },
+    Suggestion {
+        pattern: "pnpm-lock.yaml",
+        description: "pnpm lockfile",
+    },
+    Suggestion {
+        pattern: "bun.lockb",
+        description: "Bun lockfile",
+    },
+    Suggestion {
+        pattern: "poetry.lock",
+        description: "Poetry lockfile",
+    },
+    Suggestion {
+        pattern: "uv.lock",
+        description: "uv lockfile",
+    },
+    Suggestion {
+        pattern: "Gemfile.lock",
+        description: "Bundler lockfile",
+    },
+    Suggestion {
+        pattern: "go.sum",
+        description: "Go module checksums",
+    },
+    Suggestion {
+        pattern: ".github/workflows/**",
+        description: "CI workflows",
+    },
+    Suggestion {
+        pattern: "**/migrations/**",
+        description: "database migrations",
+    },
+    Suggestion {
+        pattern: "**/Dockerfile*",
+        description: "container images",
+    },
+    Suggestion {
+        pattern: "docker-compose*.yml",
+        description: "compose files",
+    },
+    Suggestion {
+        pattern: "*.tfstate*",
+        description: "Terraform state",
+    },
+    Suggestion {
+        pattern: ".terraform.lock.hcl",
+        description: "Terraform provider lockfile",
+    },
+];
 
-fn install_hook_file(path: &Path, donttouch_cmd: &str, hook_name: &str) {
-    let snippet = format!(
-        "\n# donttouch {hook_name} hook\nif command -v donttouch >/dev/null 2>&1; then\n    {donttouch_cmd}\nfi\n"
-    );
+/// Find suggestions that match at least one file under `root`, with match counts.
+fn find_suggestions(root: &Path) -> Vec<(&'static Suggestion, usize)> {
+    let compiled: Vec<(&Suggestion, Pattern)> = SUGGESTIONS
+        .iter()
+        .filter_map(|s| Pattern::new(s.pattern).ok().map(|p| (s, p)))
+        .collect();
+    let all: Vec<Pattern> = compiled.iter().map(|(_, p)| p.clone()).collect();
+    let files = discover_files(root, &all, &DiscoverSection::default());
 
-    if path.exists() {
-        let existing = std::fs::read_to_string(path).unwrap_or_default();
-        if existing.contains("donttouch") {
-            println!("   ✅ {hook_name} hook already contains donttouch.");
-            return;
-        }
-        // Append to existing hook
-        let appended = format!("{existing}{snippet}");
-        if std::fs::write(path, appended).is_ok() {
-            make_executable(path);
-            println!("   ✅ Added donttouch to existing {hook_name} hook.");
-        }
-    } else {
-        let content = format!("#!/bin/sh\n{snippet}");
-        if std::fs::write(path, content).is_ok() {
-            make_executable(path);
-            println!("   ✅ Installed {hook_name} hook.");
-        }
-    }
+    compiled
+        .iter()
+        .map(|(s, p)| {
+            let count = files
+                .iter()
+                .filter(|f| {
+                    let rel = f.path.strip_prefix(root).unwrap_or(&f.path);
+                    p.matches(&rel.to_string_lossy())
+                })
+                .count();
+            (*s, count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect()
 }
 
-fn remove_hook_donttouch(path: &Path, hook_name: &str) {
-    if !path.exists() {
-        return;
+/// Show matching suggestions as a checklist and return the ones the user picked.
+fn offer_suggestions(root: &Path) -> io::Result<Vec<String>> {
+    let found = find_suggestions(root);
+    if found.is_empty() {
+        return Ok(Vec::new());
     }
-    let content = match std::fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(_) => return,
-    };
 
-    if !content.contains("donttouch") {
-        return;
+    println!("Suggested patterns for this project:\n");
+    for (i, (s, count)) in found.iter().enumerate() {
+        println!(
+            "   [{}] {:<24} {} file(s) — {}",
+            i + 1,
+            s.pattern,
+            count,
+            s.description
+        );
     }
 
-    // Remove the donttouch block: from "# donttouch" comment through "fi\n"
-    let lines: Vec<&str> = content.lines().collect();
-    let mut new_lines: Vec<&str> = Vec::new();
-    let mut skip = false;
+    loop {
+        print!("\nProtect which? [all] / none / numbers (e.g. 1,3) ");
+        io::stdout().flush().ok();
 
-    for line in &lines {
-        if line.contains("# donttouch") {
-            skip = true;
-            continue;
-        }
-        if skip {
-            if line.trim() == "fi" {
-                skip = false;
-                continue;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+
+        match parse_selection(answer.trim(), found.len()) {
+            Some(picked) => {
+                let patterns: Vec<String> = picked
+                    .into_iter()
+                    .map(|i| found[i].0.pattern.to_string())
+                    .collect();
+                for p in &patterns {
+                    println!("   ✅ Added: {p}");
+                }
+                println!();
+                return Ok(patterns);
             }
-            continue;
+            None => println!(
+                "   ❌ Enter 'all', 'none', or numbers between 1 and {}.",
+                found.len()
+            ),
         }
-        new_lines.push(line);
     }
+}
 
-    let new_content = new_lines.join("\n") + "\n";
+/// Parse a checklist answer into zero-based indices. Returns None on invalid input.
+fn parse_selection(answer: &str, len: usize) -> Option<Vec<usize>> {
+    match answer.to_lowercase().as_str() {
+        "" | "a" | "all" | "y" | "yes" => return Some((0..len).collect()),
+        "n" | "no" | "none" => return Some(Vec::new()),
+        _ => {}
+    }
 
-    // If only shebang remains (or empty), remove the file
-    let meaningful: Vec<&str> = new_lines
-        .iter()
-        .filter(|l| !l.trim().is_empty() && !l.starts_with("#!"))
-        .copied()
-        .collect();
-
-    if meaningful.is_empty() {
-        let _ = std::fs::remove_file(path);
-        println!("   🗑️  Removed {hook_name} hook (was only donttouch).");
-    } else {
-        let _ = std::fs::write(path, new_content);
-        println!("   ✅ Removed donttouch from {hook_name} hook.");
+    let mut picked = Vec::new();
+    for part in answer.split([',', ' ']).filter(|p| !p.is_empty()) {
+        let n: usize = part.parse().ok()?;
+        if n == 0 || n > len {
+            return None;
+        }
+        if !picked.contains(&(n - 1)) {
+            picked.push(n - 1);
+        }
     }
+    Some(picked)
 }
 
-#[cfg(unix)]
-fn make_executable(path: &Path) {
-    use std::os::unix::fs::PermissionsExt;
-    let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755));
-}
-
-#[cfg(not(unix))]
-fn make_executable(_path: &Path) {}
-
 // =============================================================================
-// Agent Instruction Injection
+// Git Hook Installation
 // =============================================================================
 
-const MARKER: &str = "<!-- donttouch:managed -->";
-const INSTRUCTION: &str = "<!-- donttouch:managed --> ⚠️ donttouch is active in this project. Files marked read-only are protected — do not modify, rename, or delete them. Run `donttouch status` to see which files are protected.";
+/// Hooks donttouch manages (hook name, command the hook runs).
+const HOOKS: &[(&str, &str)] = &[
+    ("pre-commit", "donttouch check"),
+    ("commit-msg", "donttouch check-hook commit-msg \"$@\""),
+    ("pre-merge-commit", "donttouch check-hook pre-merge-commit"),
+    ("pre-rebase", "donttouch check-hook pre-rebase \"$@\""),
+    ("post-rewrite", "donttouch check-hook post-rewrite \"$@\""),
+    ("pre-push", "donttouch check-push \"$@\""),
+];
 
-const CURSOR_MDC_CONTENT: &str = r#"---
-description: donttouch file protection
----
-<!-- donttouch:managed --> ⚠️ donttouch is active in this project. Files marked read-only are protected — do not modify, rename, or delete them. Run `donttouch status` to see which files are protected.
-"#;
+/// Directory hook files live in: `.husky/` when Husky is present, otherwise
+/// `core.hooksPath` if set, otherwise the repository's hooks directory (which
+/// for worktrees and submodules is not `.git/hooks`).
+fn resolve_hooks_dir(root: &Path, has_husky: bool) -> PathBuf {
+    if has_husky {
+        return root.join(".husky");
+    }
 
-/// Agent config files we look for (path relative to root, whether to append or create).
-struct AgentTarget {
-    path: &'static str,
-    /// If true, create the file if it doesn't exist. If false, only inject into existing files.
-    create: bool,
-    /// If true, use the special Cursor MDC format
-    cursor_mdc: bool,
+    if let Some(raw) = git_output(root, &["config", "--path", "core.hooksPath"]) {
+        let configured = String::from_utf8_lossy(&raw).trim().to_string();
+        if !configured.is_empty() {
+            // Relative hooksPath is resolved from the top of the working tree
+            return root.join(configured);
+        }
+    }
+
+    git_path(root, "hooks").unwrap_or_else(|| root.join(".git/hooks"))
 }
 
-const AGENT_TARGETS: &[AgentTarget] = &[
-    AgentTarget {
-        path: "CLAUDE.md",
-        create: false,
-        cursor_mdc: false,
-    },
-    AgentTarget {
-        path: "AGENTS.md",
-        create: false,
-        cursor_mdc: false,
-    },
-    AgentTarget {
-        path: ".cursor/rules/donttouch.mdc",
-        create: true,
-        cursor_mdc: true,
-    },
-    AgentTarget {
-        path: "codex.md",
-        create: false,
-        cursor_mdc: false,
-    },
-    AgentTarget {
-        path: ".github/copilot-instructions.md",
-        create: false,
-        cursor_mdc: false,
-    },
-];
+/// Bumped whenever generated hook content changes, so `donttouch hooks upgrade`
+/// can find blocks written by older versions.
+const HOOK_VERSION: u32 = 3;
 
-fn handle_offer_inject(root: &Path) -> State {
-    // Check if any agent files exist (or cursor dir exists)
-    let has_targets = AGENT_TARGETS.iter().any(|t| {
-        let path = root.join(t.path);
-        if t.create {
-            // For cursor, check if .cursor dir exists or if we should offer anyway
-            true
-        } else {
-            path.exists()
-        }
-    });
+const BLOCK_BEGIN: &str = "# donttouch:begin";
+const BLOCK_END: &str = "# donttouch:end";
 
-    if !has_targets {
-        return State::Done {
-            message: String::new(),
-        };
+/// Whether donttouch's hooks are present and written by this version.
+#[derive(Clone, PartialEq)]
+enum HookState {
+    Missing,
+    /// Present but older or incomplete (describes what was found)
+    Outdated(String),
+    Current,
+}
+
+/// Begin marker stamped with the current hook version and the policy the block applies.
+fn block_begin(policy: HookPolicy) -> String {
+    format!("{BLOCK_BEGIN} v{HOOK_VERSION} {}", policy.name())
+}
+
+/// Version and policy on a `# donttouch:begin` line. Unstamped markers predate
+/// stamping (v1); blocks before v3 carry no policy.
+fn block_stamp(line: &str) -> Option<(u32, Option<HookPolicy>)> {
+    let rest = line.trim().strip_prefix(BLOCK_BEGIN)?;
+    if rest.is_empty() {
+        return Some((1, None));
     }
+    if !rest.starts_with(' ') {
+        return None;
+    }
+    let mut words = rest.split_whitespace();
+    let version = words.next()?.strip_prefix('v')?.parse().ok()?;
+    Some((version, words.next().and_then(HookPolicy::parse)))
+}
 
-    print!("\nAdd agent instructions to coding agent config files? [Y/n] ");
-    io::stdout().flush().ok();
+/// State for the block stamps found in one file (version 0 = unmarked legacy snippet).
+fn stamps_state(
+    stamps: impl Iterator<Item = (u32, Option<HookPolicy>)>,
+    policy: HookPolicy,
+) -> HookState {
+    let stamps: Vec<_> = stamps.collect();
+    match stamps.iter().map(|(v, _)| *v).min() {
+        None => HookState::Missing,
+        Some(0) => HookState::Outdated("unmarked donttouch snippet".into()),
+        Some(v) if v < HOOK_VERSION => HookState::Outdated(format!("v{v} donttouch block")),
+        _ => match stamps.iter().find(|(_, p)| *p != Some(policy)) {
+            Some((_, found)) => HookState::Outdated(format!(
+                "written with hook_policy {}, config says {}",
+                found.map_or("(none)", |p| p.name()),
+                policy.name()
+            )),
+            None => HookState::Current,
+        },
+    }
+}
 
-    let mut answer = String::new();
-    io::stdin().read_line(&mut answer).ok();
-    let answer = answer.trim().to_lowercase();
+/// A donttouch block in a hook script, as a line range.
+struct HookBlock {
+    start: usize,
+    end: usize,
+    version: u32,
+    policy: Option<HookPolicy>,
+}
 
-    if answer.is_empty() || answer == "y" || answer == "yes" {
-        let result = inject_agent_instructions(root, false);
-        State::Done { message: result }
-    } else {
-        State::Done {
-            message: "Ok. Run 'donttouch inject' to add agent instructions later.".into(),
+/// The snippet written before hooks had markers: comment, `if command -v`, command, `fi`.
+fn is_legacy_block(lines: &[&str], i: usize) -> bool {
+    let Some(block) = lines.get(i..i + 4) else {
+        return false;
+    };
+    let comment = block[0].trim();
+    comment.starts_with("# donttouch ")
+        && comment.ends_with(" hook")
+        && block[1].trim() == "if command -v donttouch >/dev/null 2>&1; then"
+        && block[2].trim().starts_with("donttouch ")
+        && block[3].trim() == "fi"
+}
+
+fn find_hook_blocks(lines: &[&str]) -> Vec<HookBlock> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some((version, policy)) = block_stamp(lines[i]) {
+            // An unterminated block is left alone rather than eating the rest of the hook
+            if let Some(len) = lines[i..].iter().position(|l| l.trim() == BLOCK_END) {
+                blocks.push(HookBlock {
+                    start: i,
+                    end: i + len + 1,
+                    version,
+                    policy,
+                });
+                i += len + 1;
+                continue;
+            }
+        } else if is_legacy_block(lines, i) {
+            blocks.push(HookBlock {
+                start: i,
+                end: i + 4,
+                version: 0,
+                policy: None,
+            });
+            i += 4;
+            continue;
         }
+        i += 1;
     }
+    blocks
 }
 
-fn do_inject(root: &Path, dry_run: bool) -> State {
-    let result = inject_agent_instructions(root, dry_run);
-    if dry_run {
-        State::Done {
-            message: format!("Dry run:\n{result}"),
+/// Absolute path of the running binary, embedded in per-clone hook scripts as a
+/// fallback for when `donttouch` is not on the PATH the hook runs with.
+fn installed_binary() -> Option<PathBuf> {
+    std::env::current_exe().ok()?.canonicalize().ok()
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Shell lines that run `donttouch_cmd` (from PATH, else the installing binary
+/// when `with_binary` is set) and apply `policy` when neither can be found.
+/// Files that get committed must not embed the binary: its path is machine-specific.
+fn hook_body(
+    donttouch_cmd: &str,
+    hook_name: &str,
+    policy: HookPolicy,
+    with_binary: bool,
+) -> Vec<String> {
+    let mut lines = vec![
+        "if command -v donttouch >/dev/null 2>&1; then".to_string(),
+        format!("    {donttouch_cmd} || exit $?"),
+    ];
+    if let Some(bin) = installed_binary().filter(|_| with_binary) {
+        let bin = shell_quote(&bin.to_string_lossy());
+        lines.push(format!("elif [ -x {bin} ]; then"));
+        lines.push(format!(
+            "    {} || exit $?",
+            donttouch_cmd.replacen("donttouch", &bin, 1)
+        ));
+    }
+    match policy {
+        HookPolicy::FailClosed => {
+            lines.push("else".to_string());
+            lines.push(format!(
+                "    echo \"donttouch not found; blocking {hook_name} (hook_policy = fail-closed)\" >&2"
+            ));
+            lines.push("    exit 1".to_string());
         }
-    } else {
-        State::Done { message: result }
+        HookPolicy::Warn => {
+            lines.push("else".to_string());
+            lines.push(format!(
+                "    echo \"warning: donttouch not found; skipping {hook_name} checks\" >&2"
+            ));
+        }
+        HookPolicy::Skip => {}
     }
+    lines.push("fi".to_string());
+    lines
 }
 
-fn inject_agent_instructions(root: &Path, dry_run: bool) -> String {
+/// `hook_body` as a single shell command, for managers that take one command string.
+fn hook_one_liner(donttouch_cmd: &str, hook_name: &str, policy: HookPolicy) -> String {
     let mut out = String::new();
-    let mut injected = 0;
-    let mut skipped = 0;
-
-    for target in AGENT_TARGETS {
-        let path = root.join(target.path);
+    for line in hook_body(donttouch_cmd, hook_name, policy, false) {
+        if !out.is_empty() {
+            let keyword = out.ends_with(" then") || out.ends_with(" else") || out == "else";
+            out.push_str(if keyword { " " } else { "; " });
+        }
+        out.push_str(line.trim());
+    }
+    out
+}
 
-        if path.exists() {
-            let content = match std::fs::read_to_string(&path) {
-                Ok(c) => c,
-                Err(_) => continue,
-            };
+/// The marked block donttouch puts in a hook script.
+fn hook_block(
+    donttouch_cmd: &str,
+    hook_name: &str,
+    policy: HookPolicy,
+    with_binary: bool,
+) -> String {
+    let mut lines = vec![block_begin(policy)];
+    lines.extend(hook_body(donttouch_cmd, hook_name, policy, with_binary));
+    lines.push(BLOCK_END.to_string());
+    lines.join("\n")
+}
 
-            // Already has our marker — skip
-            if content.contains(MARKER) {
-                out.push_str(&format!(
-                    "   ✅ {} (already has instructions)\n",
-                    target.path
-                ));
-                skipped += 1;
-                continue;
-            }
+fn hook_file_state(path: &Path, policy: HookPolicy) -> HookState {
+    let content = std::fs::read_to_string(path).unwrap_or_default();
+    let lines: Vec<&str> = content.lines().collect();
+    let blocks = find_hook_blocks(&lines);
+    stamps_state(blocks.iter().map(|b| (b.version, b.policy)), policy)
+}
 
-            if dry_run {
-                out.push_str(&format!("   📝 Would inject into {}\n", target.path));
-                injected += 1;
-            } else {
-                // Append instruction
-                let new_content = if content.ends_with('\n') {
-                    format!("{content}\n{INSTRUCTION}\n")
-                } else {
-                    format!("{content}\n\n{INSTRUCTION}\n")
-                };
-                match std::fs::write(&path, new_content) {
-                    Ok(()) => {
-                        out.push_str(&format!("   📝 Injected into {}\n", target.path));
-                        injected += 1;
-                    }
-                    Err(e) => {
-                        out.push_str(&format!("   ❌ Failed to write {}: {e}\n", target.path));
-                    }
-                }
-            }
-        } else if target.create && target.cursor_mdc {
-            if dry_run {
-                out.push_str(&format!("   📝 Would create {}\n", target.path));
-                injected += 1;
-            } else {
-                // Create parent dirs
-                if let Some(parent) = path.parent() {
-                    std::fs::create_dir_all(parent).ok();
-                }
-                match std::fs::write(&path, CURSOR_MDC_CONTENT) {
-                    Ok(()) => {
-                        out.push_str(&format!("   📝 Created {}\n", target.path));
-                        injected += 1;
-                    }
-                    Err(e) => {
-                        out.push_str(&format!("   ❌ Failed to create {}: {e}\n", target.path));
-                    }
-                }
-            }
-        }
-        // If file doesn't exist and create is false, silently skip
+/// Combined state of every donttouch hook in the repository.
+fn hooks_state(
+    root: &Path,
+    manager: HookManager,
+    hooks_dir: &Path,
+    policy: HookPolicy,
+) -> HookState {
+    if manager.config_file(root).is_some() {
+        return manager_state(root, manager, policy);
     }
 
-    if injected > 0 {
-        out.push_str(&format!("\n✅ Injected into {injected} file(s)."));
-    } else if skipped > 0 {
-        out.push_str("\n✅ All agent files already have instructions.");
+    let states: Vec<HookState> = HOOKS
+        .iter()
+        .map(|(hook_name, _)| hook_file_state(&hooks_dir.join(hook_name), policy))
+        .collect();
+    if states.iter().all(|s| *s == HookState::Missing) {
+        HookState::Missing
+    } else if states.iter().all(|s| *s == HookState::Current) {
+        HookState::Current
     } else {
-        out.push_str("No agent config files found to inject into.");
+        states
+            .into_iter()
+            .find(|s| matches!(s, HookState::Outdated(_)))
+            .unwrap_or_else(|| HookState::Outdated("some hooks missing".into()))
     }
+}
 
-    out
+/// Replace donttouch's `# donttouch:begin` … `# donttouch:end` blocks with `block`
+/// (in place of the first one), or remove them when `block` is None. None if the
+/// content has no blocks.
+fn rewrite_marked_blocks(content: &str, block: Option<&str>) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let blocks = find_hook_blocks(&lines);
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let mut out: Vec<&str> = Vec::new();
+    let mut next = 0;
+    for (n, b) in blocks.iter().enumerate() {
+        out.extend(&lines[next..b.start]);
+        match block {
+            Some(block) if n == 0 => out.extend(block.lines()),
+            // Drop the blank separator written in front of the block
+            _ => {
+                if out.last().is_some_and(|l| l.trim().is_empty()) {
+                    out.pop();
+                }
+            }
+        }
+        next = b.end;
+    }
+    out.extend(&lines[next..]);
+    Some(out.join("\n") + "\n")
 }
 
-/// Remove donttouch instructions from all agent files
-fn remove_agent_instructions(root: &Path) {
-    for target in AGENT_TARGETS {
-        let path = root.join(target.path);
-        if !path.exists() {
-            continue;
+/// Write hook scripts into `dir`. `with_binary` embeds the installing binary as a
+/// fallback, for hooks dirs that are never committed.
+fn install_git_hooks(
+    dir: &Path,
+    upgrade: bool,
+    policy: HookPolicy,
+    with_binary: bool,
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir).ok();
+    let mut failed = 0;
+    for (hook_name, donttouch_cmd) in HOOKS {
+        if let Err(e) = install_hook_file(
+            &dir.join(hook_name),
+            donttouch_cmd,
+            hook_name,
+            upgrade,
+            policy,
+            with_binary,
+        ) {
+            println!("   ❌ {e}");
+            failed += 1;
         }
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(format!(
+            "{n} of {} hooks could not be installed in {}.",
+            HOOKS.len(),
+            dir.display()
+        )),
+    }
+}
 
-        let content = match std::fs::read_to_string(&path) {
-            Ok(c) => c,
-            Err(_) => continue,
-        };
+/// Add donttouch's block to a hook script. Outdated blocks are rewritten in place
+/// when `upgrade` is set and reported otherwise.
+fn install_hook_file(
+    path: &Path,
+    donttouch_cmd: &str,
+    hook_name: &str,
+    upgrade: bool,
+    policy: HookPolicy,
+    with_binary: bool,
+) -> Result<(), String> {
+    let block = hook_block(donttouch_cmd, hook_name, policy, with_binary);
+    if !path.exists() {
+        let content = format!("#!/bin/sh\n\n{block}\n");
+        std::fs::write(path, content).map_err(|e| format!("Cannot write {hook_name} hook: {e}"))?;
+        make_executable(path);
+        println!("   ✅ Installed {hook_name} hook.");
+        return Ok(());
+    }
 
-        if !content.contains(MARKER) {
-            continue;
+    let existing =
+        std::fs::read_to_string(path).map_err(|e| format!("Cannot read {hook_name} hook: {e}"))?;
+    match hook_file_state(path, policy) {
+        HookState::Current => println!("   ✅ {hook_name} hook is up to date."),
+        HookState::Outdated(what) if !upgrade => {
+            println!("   ⚠️  {hook_name} hook is outdated: {what} (run 'donttouch hooks upgrade').")
         }
-
-        if target.cursor_mdc && target.create {
-            // If this is our created file, just delete it
-            let _ = std::fs::remove_file(&path);
-            println!("   🗑️  Removed {}", target.path);
-            continue;
+        HookState::Outdated(_) => {
+            let rewritten = rewrite_marked_blocks(&existing, Some(&block)).unwrap_or_default();
+            write_atomic(path, rewritten.as_bytes())?;
+            make_executable(path);
+            println!("   ⬆️  Upgraded donttouch in {hook_name} hook.");
+        }
+        HookState::Missing => {
+            let separator = if existing.ends_with('\n') { "" } else { "\n" };
+            let appended = format!("{existing}{separator}\n{block}\n");
+            write_atomic(path, appended.as_bytes())?;
+            make_executable(path);
+            println!("   ✅ Added donttouch to existing {hook_name} hook.");
         }
+    }
+    Ok(())
+}
 
-        // Remove lines containing the marker
-        let new_lines: Vec<&str> = content.lines().filter(|l| !l.contains(MARKER)).collect();
+fn plan_remove_hook_block(plan: &mut Plan, path: &Path) {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return;
+    };
+    let Some(new_content) = rewrite_marked_blocks(&content, None) else {
+        return;
+    };
 
-        // Clean up double blank lines left behind
-        let new_content = new_lines.join("\n").trim_end().to_string() + "\n";
+    // If only shebang remains (or empty), remove the file
+    let meaningful = new_content
+        .lines()
+        .any(|l| !l.trim().is_empty() && !l.starts_with("#!"));
 
-        let _ = std::fs::write(&path, new_content);
-        println!("   ✅ Removed donttouch instruction from {}", target.path);
+    if meaningful {
+        plan.write(path.to_path_buf(), new_content);
+    } else {
+        plan.delete(path.to_path_buf());
     }
 }
 
+#[cfg(unix)]
+fn make_executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755));
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) {}
+
 // =============================================================================
-// Actions (return next State)
+// Hook Managers
 // =============================================================================
 
-fn do_status(
-    config: &ConfigFile,
-    files: &[ProtectedFile],
-    enabled: bool,
-    context: &Context,
-) -> State {
-    let mut out = String::new();
-
-    if enabled {
-        out.push_str("🔒 Protection: enabled\n");
-    } else {
-        out.push_str("🔓 Protection: disabled\n");
-    }
+/// Tool that owns the repository's git hooks. Managers other than Husky
+/// regenerate `.git/hooks`, so donttouch adds itself to their config instead.
+#[derive(Clone, Copy, PartialEq)]
+enum HookManager {
+    /// Plain hook scripts in the hooks directory
+    GitHooks,
+    /// Hook scripts in `.husky/`
+    Husky,
+    /// `lefthook.yml`
+    Lefthook,
+    /// The Python `pre-commit` framework (`.pre-commit-config.yaml`)
+    PreCommit,
+    /// `simple-git-hooks` in package.json or its own JSON file
+    SimpleGitHooks,
+}
 
-    // Context info
-    match context {
-        Context::Plain => {
-            out.push_str("📁 Context: plain directory\n");
+const LEFTHOOK_FILES: &[&str] = &[
+    "lefthook.yml",
+    ".lefthook.yml",
+    "lefthook.yaml",
+    ".lefthook.yaml",
+];
+const PRE_COMMIT_FILE: &str = ".pre-commit-config.yaml";
+const SIMPLE_GIT_HOOKS_FILES: &[&str] = &[".simple-git-hooks.json", "simple-git-hooks.json"];
+
+/// Hooks that receive data on stdin (lefthook only forwards it when asked).
+const STDIN_HOOKS: &[&str] = &["pre-push", "post-rewrite"];
+
+/// The pre-commit framework can't forward post-rewrite's stdin, so that hook is skipped there.
+const PRE_COMMIT_UNSUPPORTED: &[&str] = &["post-rewrite"];
+
+impl HookManager {
+    fn detect(root: &Path) -> Self {
+        if LEFTHOOK_FILES.iter().any(|f| root.join(f).exists()) {
+            HookManager::Lefthook
+        } else if root.join(PRE_COMMIT_FILE).exists() {
+            HookManager::PreCommit
+        } else if simple_git_hooks_config(root).is_some() {
+            HookManager::SimpleGitHooks
+        } else if root.join(".husky").is_dir() {
+            HookManager::Husky
+        } else {
+            HookManager::GitHooks
         }
-        Context::Git {
-            has_husky,
-            hooks_installed,
-        } => {
-            out.push_str("📁 Context: git repository");
-            if *has_husky {
-                out.push_str(" (Husky detected)");
-            }
-            out.push('\n');
+    }
 
-            if *hooks_installed {
-                out.push_str("🪝 Hooks: installed\n");
-            } else {
-                out.push_str("🪝 Hooks: not installed (run 'donttouch init' to install)\n");
-            }
+    fn name(&self) -> &'static str {
+        match self {
+            HookManager::GitHooks => "git hooks",
+            HookManager::Husky => "Husky",
+            HookManager::Lefthook => "lefthook",
+            HookManager::PreCommit => "pre-commit",
+            HookManager::SimpleGitHooks => "simple-git-hooks",
         }
     }
 
-    out.push_str("\nPatterns:\n");
-    for p in &config.protect.patterns {
-        out.push_str(&format!("   {p}\n"));
+    /// Config file the manager keeps its hook definitions in, if it has one.
+    fn config_file(&self, root: &Path) -> Option<PathBuf> {
+        match self {
+            HookManager::GitHooks | HookManager::Husky => None,
+            HookManager::Lefthook => LEFTHOOK_FILES
+                .iter()
+                .map(|f| root.join(f))
+                .find(|p| p.exists()),
+            HookManager::PreCommit => Some(root.join(PRE_COMMIT_FILE)),
+            HookManager::SimpleGitHooks => simple_git_hooks_config(root),
+        }
     }
 
-    if files.is_empty() {
-        out.push_str("\nNo files currently match the protected patterns.");
-    } else {
-        out.push_str("\nProtected files:\n");
-        for f in files {
-            let icon = if f.readonly {
-                "🔒 read-only"
-            } else {
-                "🔓 writable"
-            };
-            out.push_str(&format!("   {icon}  {}\n", f.path.display()));
+    /// Command to run after editing the config so the manager regenerates its hooks.
+    fn apply_hint(&self) -> Option<&'static str> {
+        match self {
+            HookManager::GitHooks | HookManager::Husky => None,
+            HookManager::Lefthook => Some("lefthook install"),
+            HookManager::PreCommit => Some(
+                "pre-commit install -t pre-commit -t commit-msg -t pre-merge-commit -t pre-rebase -t pre-push",
+            ),
+            HookManager::SimpleGitHooks => Some("npx simple-git-hooks"),
         }
     }
-
-    State::Done { message: out }
 }
 
-fn do_lock(files: &[ProtectedFile]) -> State {
-    let mut out = String::new();
-    let mut locked = 0;
-    let mut already = 0;
+/// Install donttouch into whatever manages the repository's hooks. Outdated
+/// entries are replaced when `upgrade` is set and reported otherwise.
+fn install_hooks(
+    root: &Path,
+    manager: HookManager,
+    hooks_dir: &Path,
+    upgrade: bool,
+    policy: HookPolicy,
+) -> Result<(), String> {
+    let Some(path) = manager.config_file(root) else {
+        // Husky's directory is committed; only the clone's own hooks dir is private
+        return install_git_hooks(hooks_dir, upgrade, policy, manager == HookManager::GitHooks);
+    };
 
-    for f in files {
-        if f.readonly {
-            already += 1;
-        } else {
-            match set_file_readonly(&f.path, true) {
-                Ok(()) => {
-                    out.push_str(&format!("   🔒 {}\n", f.path.display()));
-                    locked += 1;
-                }
-                Err(e) => out.push_str(&format!("   ❌ {e}\n")),
-            }
+    let replace = match manager_state(root, manager, policy) {
+        HookState::Current => {
+            println!("   ✅ {} is up to date.", path.display());
+            return Ok(());
         }
-    }
-
-    // Also lock the config file itself
-    let config_path = Path::new(".donttouch.toml");
-    if !is_file_readonly(config_path) {
-        match set_file_readonly(config_path, true) {
-            Ok(()) => {
-                out.push_str("   🔒 .donttouch.toml\n");
-                locked += 1;
-            }
-            Err(e) => out.push_str(&format!("   ❌ {e}\n")),
+        HookState::Outdated(what) if !upgrade => {
+            println!(
+                "   ⚠️  {} is outdated: {what} (run 'donttouch hooks upgrade').",
+                path.display()
+            );
+            return Ok(());
         }
-    } else {
-        already += 1;
-    }
+        HookState::Outdated(_) => true,
+        HookState::Missing => false,
+    };
 
-    if locked > 0 {
-        out.push_str(&format!("\n✅ Locked {locked} file(s)."));
-    }
-    if already > 0 {
-        out.push_str(&format!("\n   ({already} already read-only)"));
+    let result = match manager {
+        HookManager::Lefthook => edit_text_config(&path, |c| {
+            lefthook_add(
+                &strip_yaml_block(c).unwrap_or_else(|| c.to_string()),
+                policy,
+            )
+        }),
+        HookManager::PreCommit => edit_text_config(&path, |c| {
+            pre_commit_add(
+                &strip_yaml_block(c).unwrap_or_else(|| c.to_string()),
+                policy,
+            )
+        }),
+        _ => edit_json_config(&path, |hooks| {
+            let removed = simple_git_hooks_remove(hooks);
+            simple_git_hooks_add(hooks, policy) || removed
+        }),
+    };
+
+    match result {
+        Ok(true) if replace => println!("   ⬆️  Upgraded donttouch in {}.", path.display()),
+        Ok(true) => println!("   ✅ Added donttouch to {}.", path.display()),
+        Ok(false) => println!("   ✅ {} already contains donttouch.", path.display()),
+        Err(e) => return Err(e),
     }
-    if locked == 0 && already > 0 {
-        out.push_str("\n✅ All protected files are already read-only.");
+    if let Some(hint) = manager.apply_hint() {
+        println!("   👉 Run '{hint}' to apply.");
     }
-
-    State::Done { message: out }
+    Ok(())
 }
 
-fn do_unlock(files: &[ProtectedFile], root: &Path) -> State {
-    let mut out = String::new();
-    let mut unlocked = 0;
+/// Remove donttouch from whatever manages the repository's hooks.
+fn remove_hooks(root: &Path, manager: HookManager, hooks_dir: &Path) -> State {
+    let mut plan = Plan::default();
+    plan_remove_hooks(&mut plan, root, manager, hooks_dir);
+    let applied = plan.apply(root);
+    let out = applied.render(&plan.notes);
+    if applied.ok() {
+        State::Done {
+            message: format!("{out}✅ Git hooks removed."),
+        }
+    } else {
+        State::Error { message: out }
+    }
+}
 
-    for f in files {
-        if f.readonly {
-            match set_file_readonly(&f.path, false) {
-                Ok(()) => {
-                    out.push_str(&format!("   🔓 {}\n", f.path.display()));
-                    unlocked += 1;
-                }
-                Err(e) => out.push_str(&format!("   ❌ {e}\n")),
+fn plan_remove_hooks(plan: &mut Plan, root: &Path, manager: HookManager, hooks_dir: &Path) {
+    let edited = match (manager, manager.config_file(root)) {
+        (HookManager::Lefthook | HookManager::PreCommit, Some(path)) => {
+            edited_text_config(&path, strip_yaml_block).map(|c| (path, c))
+        }
+        (HookManager::SimpleGitHooks, Some(path)) => {
+            edited_json_config(&path, simple_git_hooks_remove).map(|c| (path, c))
+        }
+        _ => {
+            for (hook_name, _) in HOOKS {
+                plan_remove_hook_block(plan, &hooks_dir.join(hook_name));
             }
+            return;
         }
-    }
+    };
 
-    let config_path = root.join(".donttouch.toml");
-    if is_file_readonly(&config_path) {
-        match set_file_readonly(&config_path, false) {
-            Ok(()) => {
-                out.push_str(&format!("   🔓 {}\n", config_path.display()));
-                unlocked += 1;
+    match edited {
+        Ok((path, Some(content))) => {
+            plan.write(path, content);
+            if let Some(hint) = manager.apply_hint() {
+                plan.notes.push(format!("   👉 Run '{hint}' to apply."));
             }
-            Err(e) => out.push_str(&format!("   ❌ {e}\n")),
         }
+        Ok((_, None)) => {}
+        Err(e) => plan.notes.push(format!("   ❌ {e}")),
     }
+}
 
-    if unlocked > 0 {
-        out.push_str(&format!("\n✅ Unlocked {unlocked} file(s)."));
+/// Whether a manager's config carries current donttouch entries.
+fn manager_state(root: &Path, manager: HookManager, policy: HookPolicy) -> HookState {
+    let Some(path) = manager.config_file(root) else {
+        return HookState::Missing;
+    };
+    if manager != HookManager::SimpleGitHooks {
+        let content = std::fs::read_to_string(&path).unwrap_or_default();
+        return stamps_state(content.lines().filter_map(block_stamp), policy);
+    }
+
+    // JSON has no room for markers: current means every hook starts with the command
+    // this version and policy would write
+    let Some(hooks) = read_simple_git_hooks(&path) else {
+        return HookState::Missing;
+    };
+    let command = |hook_name: &str| hooks.get(hook_name).and_then(|v| v.as_str());
+    if HOOKS.iter().all(|(hook_name, cmd)| {
+        command(hook_name).is_some_and(|c| c.starts_with(&hook_one_liner(cmd, hook_name, policy)))
+    }) {
+        HookState::Current
+    } else if hooks
+        .values()
+        .any(|v| v.as_str().is_some_and(|c| c.contains("donttouch")))
+    {
+        HookState::Outdated("some hooks missing or changed".into())
     } else {
-        out.push_str("All files were already writable.");
+        HookState::Missing
     }
+}
 
-    State::Done { message: out }
+/// Whether git will run donttouch for `hook_name`: the manager's config lists it,
+/// or the hook file calls it and is executable.
+fn hook_runs(root: &Path, manager: HookManager, hooks_dir: &Path, hook_name: &str) -> bool {
+    let Some((_, donttouch_cmd)) = HOOKS.iter().find(|(name, _)| *name == hook_name) else {
+        return false;
+    };
+    if manager.config_file(root).is_some() {
+        return manager_has_hook(root, manager, hook_name, donttouch_cmd);
+    }
+    let path = hooks_dir.join(hook_name);
+    hook_contains(&path, donttouch_cmd) && is_executable(&path)
 }
 
-fn do_check(files: &[ProtectedFile], root: &Path, context: &Context) -> State {
-    let mut issues = Vec::new();
+/// Whether a manager's config lists the donttouch command for `hook_name`.
+fn manager_has_hook(
+    root: &Path,
+    manager: HookManager,
+    hook_name: &str,
+    donttouch_cmd: &str,
+) -> bool {
+    let Some(path) = manager.config_file(root) else {
+        return false;
+    };
+    match manager {
+        HookManager::SimpleGitHooks => read_simple_git_hooks(&path)
+            .and_then(|hooks| {
+                hooks
+                    .get(hook_name)?
+                    .as_str()
+                    .map(|c| c.contains(donttouch_cmd))
+            })
+            .unwrap_or(false),
+        HookManager::Lefthook => hook_contains(&path, &lefthook_command(donttouch_cmd)),
+        _ => hook_contains(&path, strip_hook_args(donttouch_cmd)),
+    }
+}
 
-    // Check 1: permission violations (all contexts)
-    let writable: Vec<&ProtectedFile> = files.iter().filter(|f| !f.readonly).collect();
-    if !writable.is_empty() {
-        issues.push("Permission violations (files are writable):".to_string());
-        for f in &writable {
-            issues.push(format!("   • {}", f.path.display()));
-        }
+/// Apply `edit` to a text config file. Returns Ok(false) when `edit` made no change.
+fn edit_text_config(path: &Path, edit: impl Fn(&str) -> Option<String>) -> Result<bool, String> {
+    match edited_text_config(path, edit)? {
+        Some(new) => write_atomic(path, new.as_bytes()).map(|()| true),
+        None => Ok(false),
     }
+}
 
-    // Check 2: staged file violations (git context only)
-    if let Context::Git { .. } = context {
-        let patterns = files_to_patterns(root);
-        if !patterns.is_empty() {
-            let staged = get_staged_files(root);
-            let staged_violations: Vec<&String> = staged
-                .iter()
-                .filter(|f| patterns.iter().any(|p| p.matches(f)))
-                .collect();
+/// The edited content of a text config, or None if `edit` changes nothing.
+fn edited_text_config(
+    path: &Path,
+    edit: impl Fn(&str) -> Option<String>,
+) -> Result<Option<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    Ok(edit(&content))
+}
 
-            if !staged_violations.is_empty() {
-                if !issues.is_empty() {
-                    issues.push(String::new());
-                }
-                issues.push(
-                    "Staged file violations (protected files in git staging area):".to_string(),
-                );
-                for f in &staged_violations {
-                    issues.push(format!("   • {f}"));
-                }
-            }
-        }
-    }
+fn strip_hook_args(donttouch_cmd: &str) -> &str {
+    donttouch_cmd.trim_end_matches(" \"$@\"")
+}
 
-    if issues.is_empty() {
-        State::Done {
-            message: "✅ All protected files are read-only.".into(),
-        }
-    } else {
-        let mut out = String::from("🚫 donttouch check failed!\n\n");
-        for line in &issues {
-            out.push_str(line);
-            out.push('\n');
-        }
-        out.push_str("\nRun 'donttouch lock' to fix permission issues.");
-        State::Error { message: out }
-    }
+/// lefthook passes git's hook arguments through the `{0}` template.
+fn lefthook_command(donttouch_cmd: &str) -> String {
+    donttouch_cmd.replace("\"$@\"", "{0}")
 }
 
-fn do_check_push(enabled: bool, context: &Context) -> State {
-    if !context.is_git() {
-        return State::Error {
-            message: "🚫 check-push requires a git repository.".into(),
-        };
-    }
+/// Double-quoted YAML scalar, so shell syntax like `: ` and `#` stays literal.
+fn yaml_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
 
-    if !enabled {
-        State::Error {
-            message: "🚫 donttouch: push blocked! Protection is currently disabled.\n\n\
-                      You must re-enable protection before pushing:\n\
-                      \n   donttouch enable\n\n\
-                      This ensures protected files are checked before code leaves your machine."
-                .into(),
-        }
-    } else {
-        State::Done {
-            message: "✅ donttouch is enabled. Push allowed.".into(),
-        }
-    }
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
 }
 
-fn do_enable(files: &[ProtectedFile], root: &Path) -> State {
-    if let Err(e) = write_enabled(root, true) {
-        return State::Error { message: e };
+/// Add a donttouch command to every managed hook in a lefthook config.
+fn lefthook_add(content: &str, policy: HookPolicy) -> Option<String> {
+    if content.contains(BLOCK_BEGIN) {
+        return None;
     }
 
-    let mut out = String::new();
-    let mut locked = 0;
-
-    for f in files {
-        if !f.readonly && set_file_readonly(&f.path, true).is_ok() {
-            locked += 1;
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    for (hook_name, donttouch_cmd) in HOOKS {
+        let mut command = vec![
+            "donttouch:".to_string(),
+            format!(
+                "  run: {}",
+                yaml_quote(&lefthook_command(&hook_one_liner(
+                    donttouch_cmd,
+                    hook_name,
+                    policy
+                )))
+            ),
+        ];
+        if STDIN_HOOKS.contains(hook_name) {
+            command.push("  use_stdin: true".to_string());
         }
-    }
 
-    let config_path = root.join(".donttouch.toml");
-    if !is_file_readonly(&config_path) && set_file_readonly(&config_path, true).is_ok() {
-        locked += 1;
-    }
+        let hook_line = lines
+            .iter()
+            .position(|l| l.trim_end() == format!("{hook_name}:"));
+        let Some(hook_line) = hook_line else {
+            // New top-level hook
+            lines.push(String::new());
+            lines.push(block_begin(policy));
+            lines.push(format!("{hook_name}:"));
+            lines.push("  commands:".to_string());
+            lines.extend(command.iter().map(|l| format!("    {l}")));
+            lines.push(BLOCK_END.to_string());
+            continue;
+        };
 
-    if locked > 0 {
-        out.push_str(&format!("   🔒 Locked {locked} file(s).\n"));
+        // Existing hook: find its `commands:` block before the next top-level key
+        let block_end = lines[hook_line + 1..]
+            .iter()
+            .position(|l| !l.trim().is_empty() && indent_of(l) == 0)
+            .map_or(lines.len(), |i| hook_line + 1 + i);
+        let commands = (hook_line + 1..block_end).find(|&i| lines[i].trim() == "commands:");
+
+        let (at, indent, block) = match commands {
+            Some(i) => {
+                let child = lines[i + 1..block_end]
+                    .iter()
+                    .find(|l| !l.trim().is_empty())
+                    .map(|l| indent_of(l))
+                    .filter(|&n| n > indent_of(&lines[i]))
+                    .unwrap_or(indent_of(&lines[i]) + 2);
+                (i + 1, child, command)
+            }
+            None => {
+                let mut block = vec!["commands:".to_string()];
+                block.extend(command.iter().map(|l| format!("  {l}")));
+                (hook_line + 1, 2, block)
+            }
+        };
+
+        let pad = " ".repeat(indent);
+        let mut inserted = vec![format!("{pad}{}", block_begin(policy))];
+        inserted.extend(block.iter().map(|l| format!("{pad}{l}")));
+        inserted.push(format!("{pad}{BLOCK_END}"));
+        lines.splice(at..at, inserted);
     }
-    out.push_str("✅ Protection enabled.");
 
-    State::Done { message: out }
+    Some(lines.join("\n") + "\n")
 }
 
-fn do_remove(files: &[ProtectedFile], root: &Path, context: &Context) -> State {
-    let mut out = String::new();
-    let mut unlocked = 0;
-
-    for f in files {
-        if f.readonly && set_file_readonly(&f.path, false).is_ok() {
-            out.push_str(&format!("   🔓 {}\n", f.path.display()));
-            unlocked += 1;
-        }
+/// Add a local repo with donttouch hooks to a pre-commit framework config.
+fn pre_commit_add(content: &str, policy: HookPolicy) -> Option<String> {
+    if content.contains(BLOCK_BEGIN) {
+        return None;
     }
 
-    // Unlock and delete config
-    let config_path = root.join(".donttouch.toml");
-    if is_file_readonly(&config_path) {
-        let _ = set_file_readonly(&config_path, false);
-    }
-    match std::fs::remove_file(&config_path) {
-        Ok(()) => {
-            out.push_str(&format!("   🗑️  {}\n", config_path.display()));
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let repos = lines.iter().position(|l| l.starts_with("repos:"));
+    let at = match repos {
+        Some(i) => {
+            // `repos: []` has no block to add to
+            lines[i] = "repos:".to_string();
+            i + 1
         }
-        Err(e) => {
-            out.push_str(&format!(
-                "   ❌ Failed to remove {}: {e}\n",
-                config_path.display()
-            ));
+        None => {
+            lines.push("repos:".to_string());
+            lines.len()
         }
-    }
-
-    // Clean up git hooks if applicable
-    if let Context::Git { has_husky, .. } = context {
-        if *has_husky {
-            remove_hook_donttouch(&root.join(".husky/pre-commit"), "pre-commit");
-            remove_hook_donttouch(&root.join(".husky/pre-push"), "pre-push");
+    };
+    let indent = lines[at..]
+        .iter()
+        .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+        .filter(|l| l.trim_start().starts_with('-'))
+        .map_or(2, |l| indent_of(l));
+    let pad = " ".repeat(indent);
+
+    let mut block = vec![
+        block_begin(policy),
+        "- repo: local".to_string(),
+        "  hooks:".to_string(),
+    ];
+    for (hook_name, donttouch_cmd) in HOOKS {
+        if PRE_COMMIT_UNSUPPORTED.contains(hook_name) {
+            continue;
+        }
+        let id = if *hook_name == "pre-commit" {
+            "donttouch".to_string()
         } else {
-            remove_hook_donttouch(&root.join(".git/hooks/pre-commit"), "pre-commit");
-            remove_hook_donttouch(&root.join(".git/hooks/pre-push"), "pre-push");
+            format!("donttouch-{hook_name}")
+        };
+        block.push(format!("    - id: {id}"));
+        block.push(format!("      name: donttouch {hook_name}"));
+        // Run through sh so the PATH fallback and policy apply; "$@" gets the filenames
+        let script = hook_one_liner(donttouch_cmd, hook_name, policy);
+        block.push(format!(
+            "      entry: {}",
+            yaml_quote(&format!("sh -c {} donttouch", shell_quote(&script)))
+        ));
+        block.push("      language: system".to_string());
+        block.push("      always_run: true".to_string());
+        // commit-msg receives the message file as its only filename
+        if *hook_name != "commit-msg" {
+            block.push("      pass_filenames: false".to_string());
         }
+        block.push(format!("      stages: [{hook_name}]"));
     }
+    block.push(BLOCK_END.to_string());
 
-    // Clean up agent instructions
-    remove_agent_instructions(root);
-
-    if unlocked > 0 {
-        out.push_str(&format!("\n   Unlocked {unlocked} file(s)."));
-    }
-    out.push_str("\n✅ donttouch removed.");
-
-    State::Done { message: out }
+    lines.splice(at..at, block.iter().map(|l| format!("{pad}{l}")));
+    Some(lines.join("\n") + "\n")
 }
 
-fn do_why(file: &str, config: &ConfigFile) -> State {
-    let matching: Vec<(usize, &String)> = config
-        .protect
-        .patterns
-        .iter()
-        .enumerate()
-        .filter(|(_, p)| {
-            Pattern::new(p)
-                .map(|pat| pat.matches(file))
-                .unwrap_or(false)
-        })
-        .collect();
+/// Remove every `# donttouch:begin` … `# donttouch:end` block. None if there were none.
+fn strip_yaml_block(content: &str) -> Option<String> {
+    if !content.contains(BLOCK_BEGIN) {
+        return None;
+    }
 
-    if matching.is_empty() {
-        State::Done {
-            message: format!("{file} is not protected by any pattern."),
+    let mut out: Vec<&str> = Vec::new();
+    let mut skip = false;
+    for line in content.lines() {
+        match line.trim() {
+            t if block_stamp(t).is_some() => {
+                skip = true;
+                // Drop the blank separator added in front of a new top-level block
+                if indent_of(line) == 0 && out.last().is_some_and(|l| l.trim().is_empty()) {
+                    out.pop();
+                }
+            }
+            BLOCK_END => skip = false,
+            _ if !skip => out.push(line),
+            _ => {}
         }
-    } else {
-        // Read config to find the line number of the patterns array
-        let config_lines: Vec<String> = std::fs::read_to_string(".donttouch.toml")
-            .unwrap_or_default()
-            .lines()
-            .map(String::from)
-            .collect();
-
-        let mut out = format!("{file} is protected by:\n");
-        for (idx, p) in &matching {
-            // Find the line number of this pattern in the config file
-            let line_num = config_lines
-                .iter()
-                .position(|l| {
-                    let trimmed = l.trim().trim_matches(',');
-                    trimmed == format!("\"{p}\"") || trimmed == format!("'{p}'")
-                })
-                .map(|n| n + 1); // 1-indexed
+    }
 
-            if let Some(ln) = line_num {
-                out.push_str(&format!("   • {p}  (.donttouch.toml:{ln})\n"));
-            } else {
-                out.push_str(&format!("   • {p}  (pattern #{})\n", idx + 1));
-            }
+    // A `repos:` key we filled from `repos: []` is empty again
+    if let Some(i) = out.iter().position(|l| *l == "repos:") {
+        let empty = out[i + 1..]
+            .iter()
+            .find(|l| !l.trim().is_empty())
+            .is_none_or(|l| indent_of(l) == 0 && !l.starts_with('-'));
+        if empty {
+            out[i] = "repos: []";
         }
-        State::Done { message: out }
     }
+
+    Some(out.join("\n").trim_end().to_string() + "\n")
 }
 
-fn do_disable(files: &[ProtectedFile], root: &Path) -> State {
-    let config_path = root.join(".donttouch.toml");
-    if is_file_readonly(&config_path) {
-        let _ = set_file_readonly(&config_path, false);
+/// simple-git-hooks reads its config from a dedicated JSON file or package.json.
+fn simple_git_hooks_config(root: &Path) -> Option<PathBuf> {
+    if let Some(p) = SIMPLE_GIT_HOOKS_FILES
+        .iter()
+        .map(|f| root.join(f))
+        .find(|p| p.exists())
+    {
+        return Some(p);
     }
+    let package = root.join("package.json");
+    read_simple_git_hooks(&package).map(|_| package)
+}
 
-    if let Err(e) = write_enabled(root, false) {
-        return State::Error { message: e };
-    }
+/// The hook map from a simple-git-hooks config file or package.json.
+fn read_simple_git_hooks(path: &Path) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+    let hooks = if path.ends_with("package.json") {
+        json.get("simple-git-hooks")?
+    } else {
+        &json
+    };
+    hooks.as_object().cloned()
+}
 
-    let mut unlocked = 0;
-    for f in files {
-        if f.readonly && set_file_readonly(&f.path, false).is_ok() {
-            unlocked += 1;
-        }
+/// Apply `edit` to the simple-git-hooks map in a JSON file, preserving key order.
+fn edit_json_config(
+    path: &Path,
+    edit: impl Fn(&mut serde_json::Map<String, serde_json::Value>) -> bool,
+) -> Result<bool, String> {
+    match edited_json_config(path, edit)? {
+        Some(new) => write_atomic(path, new.as_bytes()).map(|()| true),
+        None => Ok(false),
     }
+}
 
-    let mut out = String::new();
-    if unlocked > 0 {
-        out.push_str(&format!("   🔓 Unlocked {unlocked} file(s).\n"));
+/// The edited content of a JSON hook config, or None if `edit` changes nothing.
+fn edited_json_config(
+    path: &Path,
+    edit: impl Fn(&mut serde_json::Map<String, serde_json::Value>) -> bool,
+) -> Result<Option<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    let mut json: serde_json::Value =
+        serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {e}", path.display()))?;
+
+    let hooks = if path.ends_with("package.json") {
+        json.get_mut("simple-git-hooks")
+    } else {
+        Some(&mut json)
+    };
+    let Some(hooks) = hooks.and_then(|h| h.as_object_mut()) else {
+        return Err(format!("{} has no simple-git-hooks config", path.display()));
+    };
+    if !edit(hooks) {
+        return Ok(None);
     }
-    out.push_str(
-        "🔓 Protection disabled.\n   ⚠️  You must run 'donttouch enable' before you can push.",
-    );
 
-    State::Done { message: out }
+    serde_json::to_string_pretty(&json)
+        .map(|out| Some(out + "\n"))
+        .map_err(|e| format!("Failed to serialize {}: {e}", path.display()))
 }
 
-// =============================================================================
-// Outside-Directory Check
-// =============================================================================
-
-fn assert_outside(target: &str) -> Result<PathBuf, String> {
-    let canonical_target = std::fs::canonicalize(target)
-        .map_err(|e| format!("Cannot resolve target path '{target}': {e}"))?;
-
-    if !canonical_target.join(".donttouch.toml").exists() {
-        return Err(format!(
-            "No .donttouch.toml found in '{}'. Is this the right directory?",
-            canonical_target.display()
-        ));
+/// Prefix each hook's command with donttouch (`donttouch … && existing`).
+fn simple_git_hooks_add(
+    hooks: &mut serde_json::Map<String, serde_json::Value>,
+    policy: HookPolicy,
+) -> bool {
+    let mut changed = false;
+    for (hook_name, donttouch_cmd) in HOOKS {
+        let existing = hooks.get(*hook_name).and_then(|v| v.as_str()).unwrap_or("");
+        if existing.contains("donttouch") {
+            continue;
+        }
+        let donttouch_cmd = hook_one_liner(donttouch_cmd, hook_name, policy);
+        let command = if existing.trim().is_empty() {
+            donttouch_cmd
+        } else {
+            format!("{donttouch_cmd} && {existing}")
+        };
+        hooks.insert(hook_name.to_string(), command.into());
+        changed = true;
     }
+    changed
+}
 
-    let canonical_cwd = std::env::current_dir()
-        .and_then(std::fs::canonicalize)
-        .map_err(|e| format!("Cannot resolve current directory: {e}"))?;
+/// Strip donttouch's command and its ` && ` (or a bare donttouch command) from every
+/// hook, including the plain `donttouch …` commands older versions wrote.
+fn simple_git_hooks_remove(hooks: &mut serde_json::Map<String, serde_json::Value>) -> bool {
+    let mut changed = false;
+    let names: Vec<String> = hooks.keys().cloned().collect();
+    for hook_name in names {
+        let Some(existing) = hooks.get(&hook_name).and_then(|v| v.as_str()) else {
+            continue;
+        };
+        let rest = if existing.starts_with("if command -v donttouch ") {
+            match existing.find("; fi") {
+                Some(i) => &existing[i + "; fi".len()..],
+                None => continue,
+            }
+        } else if existing.starts_with("donttouch ") {
+            existing.find(" && ").map_or("", |i| &existing[i..])
+        } else {
+            continue;
+        };
 
-    if canonical_cwd.starts_with(&canonical_target) {
-        return Err(format!(
-            "🚫 This command must be run from OUTSIDE the target directory.\n\n\
-             Current directory: {}\n\
-             Target directory:  {}\n\n\
-             This restriction prevents AI coding agents from disabling protection\n\
-             while working inside the project.\n\n\
-             Try: cd {} && donttouch disable {}",
-            canonical_cwd.display(),
-            canonical_target.display(),
-            canonical_target
-                .parent()
-                .map(|p| p.display().to_string())
-                .unwrap_or_else(|| "/tmp".into()),
-            canonical_target.display(),
-        ));
+        match rest.strip_prefix(" && ") {
+            Some(rest) => {
+                let rest = rest.to_string();
+                hooks.insert(hook_name, rest.into());
+            }
+            None => {
+                hooks.shift_remove(&hook_name);
+            }
+        }
+        changed = true;
     }
-
-    Ok(canonical_target)
+    changed
 }
 
 // =============================================================================
-// Git Helpers
+// Agent Instruction Injection
 // =============================================================================
 
-fn get_staged_files(root: &Path) -> Vec<String> {
-    let output = process::Command::new("git")
-        .args(["diff", "--cached", "--name-only", "--diff-filter=ACMRD"])
-        .current_dir(root)
-        .output();
-
-    match output {
-        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(String::from)
-            .collect(),
-        _ => Vec::new(),
-    }
+const MARKER: &str = "<!-- donttouch:managed -->";
+const MARKER_END: &str = "<!-- /donttouch:managed -->";
+
+/// How instructions are written into an agent file.
+#[derive(Clone, Copy)]
+enum TargetFormat<'a> {
+    /// Managed block appended to a markdown file
+    Markdown,
+    /// File owned by donttouch: frontmatter (with `{globs}` / `{globs_list}`) plus the block
+    Rule(&'a str),
+    /// Managed item in the top-level YAML list under this key
+    Yaml(&'a str),
+    /// Managed string in the top-level JSON array under this key
+    Json(&'a str),
 }
 
-/// Re-read config patterns from disk for git staged file checking.
-/// (We need the raw patterns for matching against relative paths from git.)
-fn files_to_patterns(root: &Path) -> Vec<Pattern> {
-    let config_path = root.join(".donttouch.toml");
-    let content = match std::fs::read_to_string(&config_path) {
-        Ok(c) => c,
-        Err(_) => return Vec::new(),
-    };
-    let config: ConfigFile = match toml::from_str(&content) {
-        Ok(c) => c,
-        Err(_) => return Vec::new(),
-    };
-    compile_patterns(&config.protect.patterns)
+/// When a missing agent file is created.
+#[derive(Clone, Copy)]
+enum CreateWhen<'a> {
+    Never,
+    Always,
+    /// Only if this directory exists (the agent is in use)
+    DirExists(&'a str),
 }
 
-// =============================================================================
-// Filesystem Helpers
-// =============================================================================
+/// An agent config file we write instructions to (path relative to root).
+#[derive(Clone, Copy)]
+struct AgentTarget<'a> {
+    path: &'a str,
+    format: TargetFormat<'a>,
+    create: CreateWhen<'a>,
+    /// Also write scoped instructions to files of this name in subdirectories
+    nested: bool,
+}
 
-fn compile_patterns(raw: &[String]) -> Vec<Pattern> {
-    raw.iter()
-        .filter_map(|p| match Pattern::new(p) {
-            Ok(pat) => Some(pat),
-            Err(e) => {
-                eprintln!("donttouch: bad glob pattern '{p}': {e}");
-                None
-            }
-        })
-        .collect()
+/// A target resolved to a file: the root one, or a nested copy scoped to its directory.
+struct AgentFile<'a> {
+    path: String,
+    target: AgentTarget<'a>,
+    /// Root-relative directory of a nested file
+    scope: Option<String>,
 }
 
-fn discover_files(root: &Path, patterns: &[Pattern]) -> Vec<ProtectedFile> {
-    let mut results = Vec::new();
-    walk_dir(root, root, patterns, &mut results);
-    results.sort_by(|a, b| a.path.cmp(&b.path));
-    results
-}
-
-fn walk_dir(base: &Path, dir: &Path, patterns: &[Pattern], results: &mut Vec<ProtectedFile>) {
-    let entries = match std::fs::read_dir(dir) {
-        Ok(e) => e,
-        Err(_) => return,
-    };
+const CURSOR_FRONTMATTER: &str =
+    "description: donttouch file protection\nglobs: {globs}\nalwaysApply: false";
+const WINDSURF_FRONTMATTER: &str = "trigger: glob\nglobs: {globs}";
+const CONTINUE_FRONTMATTER: &str = "name: donttouch file protection\nglobs: {globs_list}";
 
-    for entry in entries.flatten() {
-        let path = entry.path();
+/// Built-in targets. `[[inject.targets]]` adds more, or replaces one with the same path.
+/// Claude Code, Codex and Gemini CLI also read their files in subdirectories.
+const AGENT_TARGETS: &[AgentTarget<'static>] = &[
+    AgentTarget {
+        path: "CLAUDE.md",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: true,
+    },
+    AgentTarget {
+        path: "AGENTS.md",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: true,
+    },
+    AgentTarget {
+        path: ".cursor/rules/donttouch.mdc",
+        format: TargetFormat::Rule(CURSOR_FRONTMATTER),
+        create: CreateWhen::Always,
+        nested: false,
+    },
+    AgentTarget {
+        path: "codex.md",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: false,
+    },
+    AgentTarget {
+        path: ".github/copilot-instructions.md",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: false,
+    },
+    AgentTarget {
+        path: "GEMINI.md",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: true,
+    },
+    // Aider, when loaded with `--read CONVENTIONS.md`
+    AgentTarget {
+        path: "CONVENTIONS.md",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: false,
+    },
+    AgentTarget {
+        path: ".windsurfrules",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: false,
+    },
+    AgentTarget {
+        path: ".windsurf/rules/donttouch.md",
+        format: TargetFormat::Rule(WINDSURF_FRONTMATTER),
+        create: CreateWhen::DirExists(".windsurf"),
+        nested: false,
+    },
+    // Cline reads `.clinerules` as a file, or every file in it as a directory
+    AgentTarget {
+        path: ".clinerules",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: false,
+    },
+    AgentTarget {
+        path: ".clinerules/donttouch.md",
+        format: TargetFormat::Rule(""),
+        create: CreateWhen::DirExists(".clinerules"),
+        nested: false,
+    },
+    // Zed
+    AgentTarget {
+        path: ".rules",
+        format: TargetFormat::Markdown,
+        create: CreateWhen::Never,
+        nested: false,
+    },
+    AgentTarget {
+        path: ".continue/rules/donttouch.md",
+        format: TargetFormat::Rule(CONTINUE_FRONTMATTER),
+        create: CreateWhen::DirExists(".continue"),
+        nested: false,
+    },
+];
 
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            if name == ".git" || name == "target" || name == "node_modules" {
-                continue;
+impl InjectTarget {
+    fn as_target(&self) -> AgentTarget<'_> {
+        let (path, format, create) = match self {
+            InjectTarget::Markdown { path, create, .. } => (path, TargetFormat::Markdown, *create),
+            InjectTarget::Rule { path, frontmatter } => {
+                (path, TargetFormat::Rule(frontmatter), true)
             }
+            InjectTarget::Yaml { path, key, create } => (path, TargetFormat::Yaml(key), *create),
+            InjectTarget::Json { path, key, create } => (path, TargetFormat::Json(key), *create),
+        };
+        AgentTarget {
+            path,
+            format,
+            create: if create {
+                CreateWhen::Always
+            } else {
+                CreateWhen::Never
+            },
+            nested: matches!(self, InjectTarget::Markdown { nested: true, .. }),
         }
+    }
+}
+
+/// Built-in targets followed by the user's, which win on the same path.
+fn agent_targets(config: &ConfigFile) -> Vec<AgentTarget<'_>> {
+    let custom: Vec<AgentTarget> = config
+        .inject
+        .targets
+        .iter()
+        .map(InjectTarget::as_target)
+        .collect();
+    let mut targets: Vec<AgentTarget> = AGENT_TARGETS
+        .iter()
+        .filter(|b| custom.iter().all(|c| c.path != b.path))
+        .copied()
+        .collect();
+    targets.extend(custom);
+    targets
+}
 
-        let rel = path.strip_prefix(base).unwrap_or(&path);
-        let rel_str = rel.to_string_lossy();
+/// Every agent file to keep in sync: each target at the root, then nested
+/// copies of `nested` targets found below it.
+fn agent_files<'a>(root: &Path, config: &'a ConfigFile) -> Vec<AgentFile<'a>> {
+    let targets = agent_targets(config);
+    let mut files: Vec<AgentFile> = targets
+        .iter()
+        .map(|&target| AgentFile {
+            path: target.path.to_string(),
+            target,
+            scope: None,
+        })
+        .collect();
 
-        if path.is_dir() {
-            walk_dir(base, &path, patterns, results);
-        } else if patterns.iter().any(|p| p.matches(&rel_str)) {
-            results.push(ProtectedFile {
-                path: path.clone(),
-                readonly: is_file_readonly(&path),
+    let nested: Vec<&AgentTarget> = targets
+        .iter()
+        .filter(|t| t.nested && !t.path.contains('/'))
+        .collect();
+    if nested.is_empty() {
+        return files;
+    }
+    let names: Vec<&str> = nested.iter().map(|t| t.path).collect();
+    for rel in find_nested_agent_files(root, &names, &config.discover) {
+        let Some((dir, name)) = rel.rsplit_once('/') else {
+            continue;
+        };
+        if let Some(&&target) = nested.iter().find(|t| t.path == name) {
+            files.push(AgentFile {
+                scope: Some(dir.to_string()),
+                path: rel,
+                target: AgentTarget {
+                    // Nested files are only ever updated, never created
+                    create: CreateWhen::Never,
+                    ..target
+                },
             });
         }
     }
+    files
 }
 
-#[cfg(unix)]
-fn is_file_readonly(path: &Path) -> bool {
-    use std::os::unix::fs::PermissionsExt;
-    std::fs::metadata(path)
-        .map(|m| (m.permissions().mode() & 0o200) == 0)
-        .unwrap_or(false)
+/// Root-relative paths of files named one of `names` in subdirectories of
+/// `root`, found by the same walk (and cache) as protected files. Files reached
+/// through a symlink are skipped: they are injected at their real path.
+fn find_nested_agent_files(root: &Path, names: &[&str], opts: &DiscoverSection) -> Vec<String> {
+    let patterns: Vec<Pattern> = names
+        .iter()
+        .filter_map(|name| Pattern::new(&format!("*/{}", Pattern::escape(name))).ok())
+        .collect();
+    let canonical_root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let mut found: Vec<String> = discover_paths(root, &patterns, opts, "donttouch-agent-cache")
+        .into_iter()
+        .filter(|rel| {
+            std::fs::canonicalize(root.join(rel)).is_ok_and(|p| p == canonical_root.join(rel))
+        })
+        .collect();
+    found.sort();
+    found
 }
 
-#[cfg(not(unix))]
-fn is_file_readonly(path: &Path) -> bool {
-    std::fs::metadata(path)
-        .map(|m| m.permissions().readonly())
-        .unwrap_or(false)
+impl AgentTarget<'_> {
+    fn may_create(&self, root: &Path) -> bool {
+        match self.create {
+            CreateWhen::Never => false,
+            CreateWhen::Always => true,
+            CreateWhen::DirExists(dir) => root.join(dir).is_dir(),
+        }
+    }
+
+    /// `existing` with the managed instructions set to `block`, or removed when None.
+    fn render(
+        &self,
+        existing: &str,
+        config: &ConfigFile,
+        block: Option<&str>,
+    ) -> Result<String, String> {
+        match (self.format, block) {
+            (TargetFormat::Rule(frontmatter), Some(block)) => {
+                Ok(render_rule_file(frontmatter, config, block))
+            }
+            (TargetFormat::Rule(_), None) => Ok(String::new()),
+            (TargetFormat::Markdown, Some(block)) => {
+                Ok(match rewrite_instruction_block(existing, Some(block)) {
+                    Some(updated) => updated,
+                    None if existing.is_empty() => format!("{block}\n"),
+                    None if existing.ends_with('\n') => format!("{existing}\n{block}\n"),
+                    None => format!("{existing}\n\n{block}\n"),
+                })
+            }
+            (TargetFormat::Markdown, None) => {
+                Ok(rewrite_instruction_block(existing, None).unwrap_or_else(|| existing.into()))
+            }
+            (TargetFormat::Yaml(key), block) => yaml_instructions(existing, key, block),
+            (TargetFormat::Json(key), block) => json_instructions(existing, key, block),
+        }
+    }
 }
 
-#[cfg(unix)]
-fn set_file_readonly(path: &Path, readonly: bool) -> Result<(), String> {
-    use std::os::unix::fs::PermissionsExt;
-    let meta =
-        std::fs::metadata(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
-    let mut mode = meta.permissions().mode();
-    if readonly {
-        mode &= !0o222;
+/// The managed instruction block, listing the current patterns. A nested
+/// file's block (`scope` = its directory) lists only patterns that can match below it.
+fn render_instructions(config: &ConfigFile, scope: Option<&str>) -> String {
+    let entries: Vec<&PatternEntry> = config
+        .protect
+        .entries
+        .iter()
+        .filter(|e| {
+            scope.is_none_or(|dir| may_contain_matches(&[literal_prefix(e.pattern())], dir))
+        })
+        .collect();
+    let mut lines = vec![
+        MARKER.to_string(),
+        "## Protected Files (donttouch)".into(),
+        String::new(),
+    ];
+    if entries.is_empty() && scope.is_some() {
+        lines.push(
+            "donttouch is active in this repository, but nothing under this directory is protected."
+                .into(),
+        );
+    } else if entries.is_empty() {
+        lines.push("donttouch is active in this project, but no files are protected yet.".into());
     } else {
-        mode |= 0o200;
+        lines.push(match scope {
+            Some(_) => "The following files are protected by donttouch and must not be modified \
+                        (patterns are relative to the repository root):"
+                .into(),
+            None => {
+                "The following files are protected by donttouch and must not be modified:".into()
+            }
+        });
+        for e in entries {
+            match e.note() {
+                Some(note) => lines.push(format!("- `{}` — {note}", e.pattern())),
+                None => lines.push(format!("- `{}`", e.pattern())),
+            }
+        }
+        lines.push(String::new());
+        lines.push(
+            "Do not edit, move, rename, or delete these files, or change their permissions. \
+             If a task requires changing one, stop and ask the user."
+                .into(),
+        );
     }
-    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
-        .map_err(|e| format!("Cannot set permissions on {}: {e}", path.display()))
+    lines.push(MARKER_END.into());
+    lines.join("\n")
 }
 
-#[cfg(not(unix))]
-fn set_file_readonly(path: &Path, readonly: bool) -> Result<(), String> {
-    let meta =
-        std::fs::metadata(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
-    let mut perms = meta.permissions();
-    perms.set_readonly(readonly);
-    std::fs::set_permissions(path, perms)
-        .map_err(|e| format!("Cannot set permissions on {}: {e}", path.display()))
+/// A rule file: frontmatter attaching it to the protected files, then the block.
+fn render_rule_file(frontmatter: &str, config: &ConfigFile, block: &str) -> String {
+    if frontmatter.is_empty() {
+        return format!("{block}\n");
+    }
+    let mut globs: Vec<String> = config
+        .protect
+        .patterns()
+        .iter()
+        .map(|p| rule_glob(p))
+        .collect();
+    if globs.is_empty() {
+        globs.push("**".into());
+    }
+    let frontmatter = frontmatter
+        .replace(
+            "{globs_list}",
+            &serde_json::to_string(&globs).unwrap_or_default(),
+        )
+        .replace("{globs}", &globs.join(","));
+    format!("---\n{frontmatter}\n---\n{block}\n")
 }
 
-fn write_enabled(root: &Path, enabled: bool) -> Result<(), String> {
-    let config_path = root.join(".donttouch.toml");
-    let content = std::fs::read_to_string(&config_path)
-        .map_err(|e| format!("Could not read {}: {e}", config_path.display()))?;
+/// Rule globs are root-relative but `*` stops at `/`, unlike donttouch's.
+fn rule_glob(pattern: &str) -> String {
+    if !pattern.contains('/') && pattern.starts_with('*') && !pattern.starts_with("**") {
+        format!("**/{pattern}")
+    } else {
+        pattern.to_string()
+    }
+}
 
-    let new_content = if content.contains("enabled") {
-        content
-            .lines()
-            .map(|line| {
-                if line.trim().starts_with("enabled") && line.contains('=') {
-                    format!("enabled = {enabled}")
-                } else {
-                    line.to_string()
-                }
-            })
-            .collect::<Vec<_>>()
-            .join("\n")
-            + "\n"
+/// Line range of the managed block, including a legacy one-line instruction.
+fn find_instruction_block(lines: &[&str]) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|l| l.contains(MARKER))?;
+    if lines[start].trim() != MARKER {
+        return Some((start, start));
+    }
+    let end = lines[start..]
+        .iter()
+        .position(|l| l.trim() == MARKER_END)
+        .map_or(start, |i| start + i);
+    Some((start, end))
+}
+
+/// Replace the managed block with `block`, or remove it when `None`.
+fn rewrite_instruction_block(content: &str, block: Option<&str>) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let (start, end) = find_instruction_block(&lines)?;
+    let mut out: Vec<&str> = lines[..start].to_vec();
+    let rest = &lines[end + 1..];
+    match block {
+        Some(block) => out.extend(block.lines()),
+        // Drop the blank line that separated the block, so none pile up
+        None if out.last().is_some_and(|l| l.trim().is_empty())
+            && rest.first().is_none_or(|l| l.trim().is_empty()) =>
+        {
+            out.pop();
+        }
+        None => {}
+    }
+    out.extend(rest);
+    let joined = out.join("\n");
+    let trimmed = joined.trim_end();
+    Some(if trimmed.is_empty() {
+        String::new()
     } else {
-        let mut lines: Vec<String> = content.lines().map(String::from).collect();
-        if let Some(idx) = lines.iter().position(|l| l.trim() == "[protect]") {
-            lines.insert(idx + 1, format!("enabled = {enabled}"));
+        format!("{trimmed}\n")
+    })
+}
+
+/// Set the instructions as a `- |` item of the top-level YAML list `key`,
+/// between `# donttouch:begin` / `# donttouch:end`, or remove them.
+fn yaml_instructions(content: &str, key: &str, block: Option<&str>) -> Result<String, String> {
+    let item = |indent: usize| {
+        let pad = " ".repeat(indent);
+        let mut lines = vec![format!("{pad}{BLOCK_BEGIN}"), format!("{pad}- |")];
+        for l in block.unwrap_or_default().lines() {
+            lines.push(if l.is_empty() {
+                String::new()
+            } else {
+                format!("{pad}  {l}")
+            });
         }
-        lines.join("\n") + "\n"
+        lines.push(format!("{pad}{BLOCK_END}"));
+        lines.join("\n")
+    };
+
+    let lines: Vec<&str> = content.lines().collect();
+    if let Some(existing) = lines.iter().find(|l| block_stamp(l).is_some()) {
+        let replacement = block.map(|_| item(indent_of(existing)));
+        let mut updated = rewrite_marked_blocks(content, replacement.as_deref())
+            .unwrap_or_else(|| content.into());
+        // Leave a list we emptied as `key: []` rather than null
+        if block.is_none() {
+            let mut out: Vec<&str> = updated.lines().collect();
+            if let Some(i) = out.iter().position(|l| *l == format!("{key}:")) {
+                let empty = out[i + 1..]
+                    .iter()
+                    .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+                    .is_none_or(|l| indent_of(l) == 0 && !l.starts_with('-'));
+                if empty {
+                    let emptied = format!("{key}: []");
+                    out[i] = &emptied;
+                    updated = out.join("\n") + "\n";
+                }
+            }
+            // Nothing left but the list we added: the file was ours
+            if updated.trim() == format!("{key}: []") {
+                return Ok(String::new());
+            }
+        }
+        return Ok(updated);
+    }
+    if block.is_none() {
+        return Ok(content.into());
+    }
+
+    let head = format!("{key}:");
+    let mut out: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    match lines.iter().position(|l| l.starts_with(&head)) {
+        Some(i) => {
+            let value = lines[i][head.len()..].trim();
+            if value == "[]" {
+                out[i] = head;
+            } else if !value.is_empty() && !value.starts_with('#') {
+                return Err(format!("'{key}' is not a block list"));
+            }
+            // Match the indentation of the existing items
+            let indent = lines[i + 1..]
+                .iter()
+                .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+                .filter(|l| l.trim_start().starts_with('-'))
+                .map_or(2, |l| indent_of(l));
+            out.insert(i + 1, item(indent));
+        }
+        None => {
+            if out.last().is_some_and(|l| !l.trim().is_empty()) {
+                out.push(String::new());
+            }
+            out.push(head);
+            out.push(item(2));
+        }
+    }
+    Ok(out.join("\n") + "\n")
+}
+
+/// Set the instructions as the managed string in the top-level JSON array
+/// `key`, or remove it.
+fn json_instructions(content: &str, key: &str, block: Option<&str>) -> Result<String, String> {
+    let mut json: serde_json::Value = if content.trim().is_empty() {
+        serde_json::json!({})
+    } else {
+        serde_json::from_str(content).map_err(|e| format!("invalid JSON: {e}"))?
     };
+    let Some(object) = json.as_object_mut() else {
+        return Err("not a JSON object".into());
+    };
+    let list = object
+        .entry(key)
+        .or_insert_with(|| serde_json::json!([]))
+        .as_array_mut()
+        .ok_or_else(|| format!("'{key}' is not an array"))?;
+
+    let ours = |v: &serde_json::Value| v.as_str().is_some_and(|s| s.contains(MARKER));
+    let current: Vec<&str> = list
+        .iter()
+        .filter(|v| ours(v))
+        .filter_map(|v| v.as_str())
+        .collect();
+    if current == block.into_iter().collect::<Vec<_>>() {
+        return Ok(content.into());
+    }
+    list.retain(|v| !ours(v));
+    if let Some(block) = block {
+        list.push(block.into());
+    } else if list.is_empty() && object.len() == 1 {
+        // Nothing left but the array we added: the file was ours
+        return Ok(String::new());
+    }
 
-    std::fs::write(&config_path, new_content)
-        .map_err(|e| format!("Failed to write {}: {e}", config_path.display()))
+    serde_json::to_string_pretty(&json)
+        .map(|s| s + "\n")
+        .map_err(|e| e.to_string())
 }
 
-// =============================================================================
-// Main
-// =============================================================================
+/// Root-relative paths of agent files that carry donttouch instructions.
+fn instructed_agent_files(root: &Path, config: &ConfigFile) -> Vec<String> {
+    agent_files(root, config)
+        .into_iter()
+        .map(|f| f.path)
+        .filter(|p| hook_contains(&root.join(p), MARKER))
+        .collect()
+}
 
-fn main() {
-    let cli = Cli::parse();
-    let start = State::Start {
-        command: cli.command,
-        ignoregit: cli.ignoregit,
+/// What injecting did (or would do) to one agent file.
+enum InjectOutcome {
+    UpToDate,
+    Updated,
+    Injected,
+    Created,
+    Failed(String),
+}
+
+/// Plan bringing every agent file's instructions in line with the config. With
+/// `update_only`, files that don't have instructions yet are left alone.
+fn sync_agent_instructions(
+    root: &Path,
+    config: &ConfigFile,
+    update_only: bool,
+) -> (Vec<(String, InjectOutcome)>, Plan) {
+    let mut results = Vec::new();
+    let mut plan = Plan::default();
+
+    for AgentFile {
+        path: rel,
+        target,
+        scope,
+    } in agent_files(root, config)
+    {
+        let path = root.join(&rel);
+
+        let existing = if path.is_file() {
+            match std::fs::read_to_string(&path) {
+                Ok(c) => Some(c),
+                Err(_) => continue,
+            }
+        } else {
+            None
+        };
+        let outcome = match &existing {
+            None if update_only || !target.may_create(root) => continue,
+            None => InjectOutcome::Created,
+            Some(c) if c.contains(MARKER) => InjectOutcome::Updated,
+            // Not ours (a rule file), or nothing to update
+            Some(_) if update_only || matches!(target.format, TargetFormat::Rule(_)) => continue,
+            Some(_) => InjectOutcome::Injected,
+        };
+
+        let existing = existing.unwrap_or_default();
+        let block = render_instructions(config, scope.as_deref());
+        let new_content = match target.render(&existing, config, Some(&block)) {
+            Ok(c) => c,
+            Err(e) => {
+                results.push((rel, InjectOutcome::Failed(e)));
+                continue;
+            }
+        };
+        if new_content == existing {
+            results.push((rel, InjectOutcome::UpToDate));
+            continue;
+        }
+
+        // Agent files locked by `[inject] protect` are written through the lock
+        plan.write(path, new_content);
+        results.push((rel, outcome));
+    }
+
+    (results, plan)
+}
+
+fn handle_offer_inject(root: &Path) -> State {
+    let config: ConfigFile = match std::fs::read_to_string(root.join(".donttouch.toml"))
+        .map_err(|e| e.to_string())
+        .and_then(|c| toml::from_str(&c).map_err(|e| e.to_string()))
+    {
+        Ok(c) => c,
+        Err(e) => {
+            return State::Error {
+                message: format!("Invalid config: {e}"),
+            };
+        }
     };
-    start.run();
+
+    // Offer only if some agent file exists or would be created
+    let has_targets = agent_files(root, &config)
+        .iter()
+        .any(|f| f.target.may_create(root) || root.join(&f.path).is_file());
+
+    if !has_targets {
+        return State::Done {
+            message: String::new(),
+        };
+    }
+
+    print!("\nAdd agent instructions to coding agent config files? [Y/n] ");
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok();
+    let answer = answer.trim().to_lowercase();
+
+    if answer.is_empty() || answer == "y" || answer == "yes" {
+        match inject_agent_instructions(root, &config, false, false) {
+            Ok(message) => State::Done { message },
+            Err(message) => State::Error { message },
+        }
+    } else {
+        State::Done {
+            message: "Ok. Run 'donttouch inject' to add agent instructions later.".into(),
+        }
+    }
+}
+
+fn do_inject(config: &ConfigFile, root: &Path, dry_run: bool, update: bool) -> State {
+    match inject_agent_instructions(root, config, update, dry_run) {
+        Ok(result) if dry_run => State::Done {
+            message: format!("Dry run:\n{result}"),
+        },
+        Ok(message) => State::Done { message },
+        Err(message) => State::Error { message },
+    }
+}
+
+fn inject_agent_instructions(
+    root: &Path,
+    config: &ConfigFile,
+    update_only: bool,
+    dry_run: bool,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut changed = 0;
+    let mut current = 0;
+    let would = if dry_run { "Would " } else { "" };
+
+    let (outcomes, plan) = sync_agent_instructions(root, config, update_only);
+    // Don't write some files when others can't be rendered
+    let unrenderable: Vec<String> = outcomes
+        .iter()
+        .filter_map(|(path, outcome)| match outcome {
+            InjectOutcome::Failed(e) => Some(format!("   ❌ Failed to write {path}: {e}\n")),
+            _ => None,
+        })
+        .collect();
+    if !dry_run && !unrenderable.is_empty() {
+        return Err(unrenderable.concat() + "\nNo agent files were changed.");
+    }
+    if !dry_run {
+        let applied = plan.apply(root);
+        if !applied.ok() {
+            return Err(applied.render(&plan.notes));
+        }
+    }
+
+    for (path, outcome) in outcomes {
+        match outcome {
+            InjectOutcome::UpToDate => {
+                out.push_str(&format!("   ✅ {path} (already up to date)\n"));
+                current += 1;
+            }
+            InjectOutcome::Updated => {
+                let verb = if dry_run { "update" } else { "Updated" };
+                out.push_str(&format!("   📝 {would}{verb} {path}\n"));
+                changed += 1;
+            }
+            InjectOutcome::Injected => {
+                let verb = if dry_run {
+                    "inject into"
+                } else {
+                    "Injected into"
+                };
+                out.push_str(&format!("   📝 {would}{verb} {path}\n"));
+                changed += 1;
+            }
+            InjectOutcome::Created => {
+                let verb = if dry_run { "create" } else { "Created" };
+                out.push_str(&format!("   📝 {would}{verb} {path}\n"));
+                changed += 1;
+            }
+            InjectOutcome::Failed(e) => {
+                out.push_str(&format!("   ❌ Failed to write {path}: {e}\n"));
+            }
+        }
+    }
+    if changed > 0 {
+        let verb = if dry_run { "Would write" } else { "Wrote" };
+        out.push_str(&format!("\n✅ {verb} instructions to {changed} file(s)."));
+    } else if current > 0 {
+        out.push_str("\n✅ All agent files already have up-to-date instructions.");
+    } else if update_only {
+        out.push_str("No agent files have donttouch instructions (run 'donttouch inject').");
+    } else {
+        out.push_str("No agent config files found to inject into.");
+    }
+    if dry_run && !plan.is_empty() {
+        out.push_str("\n\n");
+        out.push_str(&plan.diffs(root));
+    }
+
+    Ok(out)
+}
+
+/// Plan removing donttouch instructions from all agent files
+fn plan_remove_agent_instructions(plan: &mut Plan, root: &Path, config: &ConfigFile) {
+    for file in agent_files(root, config) {
+        let path = root.join(&file.path);
+        if !path.is_file() {
+            continue;
+        }
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        if !content.contains(MARKER) {
+            continue;
+        }
+
+        match file.target.render(&content, config, None) {
+            // Nothing but our instructions (a rule file, or one we created): delete it
+            Ok(new_content) if new_content.trim().is_empty() => plan.delete(path),
+            Ok(new_content) => plan.write(path, new_content),
+            Err(e) => plan
+                .notes
+                .push(format!("   ⚠️  Could not update {}: {e}", file.path)),
+        }
+    }
+}
+
+// =============================================================================
+// Exports
+// =============================================================================
+
+/// Where GitHub and GitLab look for CODEOWNERS, in lookup order.
+const CODEOWNERS_FILES: &[&str] = &[
+    ".github/CODEOWNERS",
+    "CODEOWNERS",
+    "docs/CODEOWNERS",
+    ".gitlab/CODEOWNERS",
+];
+
+fn codeowners_file(root: &Path) -> PathBuf {
+    CODEOWNERS_FILES
+        .iter()
+        .map(|f| root.join(f))
+        .find(|p| p.exists())
+        .unwrap_or_else(|| root.join(CODEOWNERS_FILES[0]))
+}
+
+/// Translate a donttouch pattern into a gitignore-style path (CODEOWNERS and agent
+/// ignore files). Ok carries the path and, when the translation is not exact, a
+/// warning; Err means it can't be expressed.
+///
+/// donttouch globs are root-relative and their `*` also matches `/`. gitignore
+/// rules match slash-less entries at any depth, and their `*` stops at `/`.
+fn gitignore_path(pattern: &str) -> Result<(String, Option<&'static str>), &'static str> {
+    if pattern.starts_with('!') || pattern.starts_with('#') {
+        return Err("a leading '!' or '#' would change the rule's meaning");
+    }
+
+    let p = pattern.trim_start_matches("./").trim_start_matches('/');
+    let segments: Vec<&str> = p.split('/').collect();
+    let (last, dirs) = segments.split_last().unwrap_or((&"", &[]));
+    let mut warning = None;
+
+    let path = if dirs.is_empty() && last.starts_with('*') && !last.starts_with("**") {
+        // `*.lock` matches at any depth in both
+        if last[1..].contains('*') {
+            warning = Some("'*' matches across directories in donttouch but not in CODEOWNERS");
+        }
+        last.to_string()
+    } else {
+        let mut out = String::new();
+        for dir in dirs {
+            if dir.contains('*') && *dir != "**" {
+                warning = Some("'*' matches across directories in donttouch but not in CODEOWNERS");
+            }
+            out.push('/');
+            out.push_str(dir);
+        }
+        // `dir/*.rs` also covers files in subdirectories of `dir`
+        if last.starts_with('*')
+            && !last.starts_with("**")
+            && dirs.last().is_some_and(|d| *d != "**")
+        {
+            out.push_str("/**");
+        }
+        out.push('/');
+        out.push_str(last);
+        out
+    };
+
+    Ok((path.replace(' ', "\\ "), warning))
+}
+
+/// The managed CODEOWNERS block, plus warnings for patterns that didn't translate cleanly.
+fn codeowners_block(entries: &[PatternEntry], owners: &[String]) -> (String, Vec<String>) {
+    let default_owners = owners.join(" ");
+    let mut warnings = Vec::new();
+    let mut lines = vec![
+        BLOCK_BEGIN.to_string(),
+        "# Generated by 'donttouch export codeowners' from .donttouch.toml — edits are overwritten."
+            .to_string(),
+    ];
+    for entry in entries {
+        let pattern = entry.pattern();
+        // A pattern's own owner replaces --owner
+        let owners = entry.owner().unwrap_or(&default_owners);
+        if owners.is_empty() {
+            warnings.push(format!("'{pattern}' skipped: no owner (pass --owner)."));
+            continue;
+        }
+        if pattern.contains('[') {
+            warnings.push(format!(
+                "'{pattern}' skipped: character classes ([...]) are not supported by CODEOWNERS."
+            ));
+            continue;
+        }
+        match gitignore_path(pattern) {
+            Ok((path, warning)) => {
+                if let Some(w) = warning {
+                    warnings.push(format!("'{pattern}' → '{path}' is approximate: {w}."));
+                }
+                lines.push(format!("{path} {owners}"));
+            }
+            Err(reason) => warnings.push(format!("'{pattern}' skipped: {reason}.")),
+        }
+    }
+    lines.push(BLOCK_END.to_string());
+    (lines.join("\n"), warnings)
+}
+
+fn do_export(target: &ExportTarget, config: &ConfigFile, root: &Path) -> State {
+    match target {
+        ExportTarget::Codeowners {
+            owner,
+            file,
+            dry_run,
+        } => export_codeowners(config, root, owner, file.as_deref(), *dry_run),
+        ExportTarget::Ignore { all, dry_run } => export_ignore(config, root, *all, *dry_run),
+    }
+}
+
+fn export_codeowners(
+    config: &ConfigFile,
+    root: &Path,
+    owners: &[String],
+    file: Option<&Path>,
+    dry_run: bool,
+) -> State {
+    let path = file.map_or_else(|| codeowners_file(root), |f| root.join(f));
+    let entries = &config.protect.entries;
+    if owners.is_empty() && entries.iter().all(|e| e.owner().is_none()) {
+        return State::Error {
+            message: "❌ No owners: pass --owner, or set `owner` on patterns in .donttouch.toml."
+                .into(),
+        };
+    }
+    let (block, warnings) = codeowners_block(entries, owners);
+
+    let existing = std::fs::read_to_string(&path).unwrap_or_default();
+    let updated = match rewrite_marked_blocks(&existing, Some(&block)) {
+        Some(updated) => updated,
+        // Appended last so these rules win over earlier ones (last match wins)
+        None if existing.trim().is_empty() => format!("{block}\n"),
+        None => format!("{}\n\n{block}\n", existing.trim_end()),
+    };
+
+    let mut out = String::new();
+    let pattern_owners = entries
+        .iter()
+        .filter_map(|e| e.owner())
+        .flat_map(str::split_whitespace);
+    let mut seen = Vec::new();
+    for owner in owners.iter().map(String::as_str).chain(pattern_owners) {
+        if seen.contains(&owner) {
+            continue;
+        }
+        seen.push(owner);
+        if !owner.contains('@') {
+            out.push_str(&format!(
+                "   ⚠️  '{owner}' doesn't look like an owner (@user, @org/team or an email).\n"
+            ));
+        }
+    }
+    for w in &warnings {
+        out.push_str(&format!("   ⚠️  {w}\n"));
+    }
+
+    let shown = path.strip_prefix(root).unwrap_or(&path).display();
+    if updated == existing {
+        out.push_str(&format!("✅ {shown} is up to date."));
+    } else if dry_run {
+        out.push_str(&format!("Dry run — would write to {shown}:\n\n{block}"));
+    } else {
+        if let Err(e) = write_atomic(&path, updated.as_bytes()) {
+            return State::Error {
+                message: format!("❌ {e}"),
+            };
+        }
+        out.push_str(&format!("📝 Updated {shown}."));
+    }
+    out.push_str(
+        "\n👉 Turn on 'Require review from Code Owners' in the default branch's protection \
+         rules (GitHub) or 'Code owner approval' (GitLab) so these owners must approve.",
+    );
+    State::Done { message: out }
+}
+
+/// Ignore files coding agents read to keep paths out of context (file, tool,
+/// whether to create it when missing).
+const AGENT_IGNORE_FILES: &[(&str, &str, bool)] = &[
+    (".cursorignore", "Cursor", true),
+    (".aiexclude", "Gemini in Android Studio", true),
+    (".codeiumignore", "Windsurf / Codeium", true),
+    (".geminiignore", "Gemini CLI", false),
+    (".aiderignore", "Aider", false),
+    (".clineignore", "Cline", false),
+];
+
+fn export_ignore(config: &ConfigFile, root: &Path, all: bool, dry_run: bool) -> State {
+    let patterns: Vec<&str> = config
+        .protect
+        .entries
+        .iter()
+        .filter(|e| all || e.hide())
+        .map(PatternEntry::pattern)
+        .collect();
+    if patterns.is_empty() {
+        return State::Done {
+            message: "No patterns are marked hide = true. Mark them in .donttouch.toml:\n\n   \
+                      patterns = [\"Cargo.lock\", { pattern = \".env*\", hide = true }]\n\n\
+                      or run 'donttouch export ignore --all' to hide every protected file."
+                .into(),
+        };
+    }
+
+    let mut out = String::new();
+    let mut lines = vec![
+        BLOCK_BEGIN.to_string(),
+        "# Generated by 'donttouch export ignore' from .donttouch.toml — edits are overwritten."
+            .to_string(),
+    ];
+    for pattern in patterns {
+        match gitignore_path(pattern) {
+            Ok((path, warning)) => {
+                if let Some(w) = warning {
+                    out.push_str(&format!(
+                        "   ⚠️  '{pattern}' → '{path}' is approximate: {w}.\n"
+                    ));
+                }
+                lines.push(path);
+            }
+            Err(reason) => out.push_str(&format!("   ⚠️  '{pattern}' skipped: {reason}.\n")),
+        }
+    }
+    lines.push(BLOCK_END.to_string());
+    let block = lines.join("\n");
+
+    for (file, tool, create) in AGENT_IGNORE_FILES {
+        let path = root.join(file);
+        if !path.exists() && !create {
+            continue;
+        }
+        let existing = std::fs::read_to_string(&path).unwrap_or_default();
+        let updated = match rewrite_marked_blocks(&existing, Some(&block)) {
+            Some(updated) => updated,
+            None if existing.trim().is_empty() => format!("{block}\n"),
+            None => format!("{}\n\n{block}\n", existing.trim_end()),
+        };
+
+        if updated == existing {
+            out.push_str(&format!("   ✅ {file} ({tool}) is up to date\n"));
+        } else if dry_run {
+            out.push_str(&format!("   📝 Would write {file} ({tool})\n"));
+        } else {
+            match write_atomic(&path, updated.as_bytes()) {
+                Ok(()) => out.push_str(&format!("   📝 Updated {file} ({tool})\n")),
+                Err(e) => out.push_str(&format!("   ❌ {e}\n")),
+            }
+        }
+    }
+
+    if dry_run {
+        out.push_str(&format!("\nDry run — block:\n\n{block}"));
+    } else {
+        out.push_str("\n✅ Agent ignore files in sync.");
+    }
+    State::Done { message: out }
+}
+
+/// Remove managed blocks from agent ignore files, deleting files left empty.
+fn plan_remove_ignore_blocks(plan: &mut Plan, root: &Path) {
+    for (file, _, _) in AGENT_IGNORE_FILES {
+        let path = root.join(file);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let Some(stripped) = rewrite_marked_blocks(&content, None) else {
+            continue;
+        };
+        if stripped.trim().is_empty() {
+            plan.delete(path);
+        } else {
+            plan.write(path, stripped);
+        }
+    }
+}
+
+/// Remove the managed CODEOWNERS block, deleting the file if nothing else is left.
+fn plan_remove_codeowners_block(plan: &mut Plan, root: &Path) {
+    for path in CODEOWNERS_FILES.iter().map(|f| root.join(f)) {
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let Some(stripped) = rewrite_marked_blocks(&content, None) else {
+            continue;
+        };
+        if stripped.trim().is_empty() {
+            plan.delete(path);
+        } else {
+            plan.write(path, stripped);
+        }
+    }
+}
+
+// =============================================================================
+// Actions (return next State)
+// =============================================================================
+
+fn do_status(
+    config: &ConfigFile,
+    files: &[ProtectedFile],
+    enabled: bool,
+    root: &Path,
+    context: &Context,
+) -> State {
+    let mut out = String::new();
+
+    if enabled {
+        out.push_str("🔒 Protection: enabled\n");
+    } else {
+        out.push_str("🔓 Protection: disabled\n");
+    }
+
+    // Context info
+    match context {
+        Context::Plain => {
+            out.push_str("📁 Context: plain directory\n");
+        }
+        Context::Git {
+            manager, hooks_dir, ..
+        } => {
+            out.push_str("📁 Context: git repository");
+            if *manager != HookManager::GitHooks {
+                out.push_str(&format!(" ({} detected)", manager.name()));
+            }
+            out.push('\n');
+
+            let location = manager
+                .config_file(root)
+                .unwrap_or_else(|| hooks_dir.clone());
+            let policy = config.protect.hook_policy;
+            match hooks_state(root, *manager, hooks_dir, policy) {
+                HookState::Current => {
+                    out.push_str(&format!("🪝 Hooks: installed ({})\n", location.display()))
+                }
+                HookState::Outdated(what) => out.push_str(&format!(
+                    "🪝 Hooks: outdated — {what} ({}; run 'donttouch hooks upgrade')\n",
+                    location.display()
+                )),
+                HookState::Missing => out.push_str(
+                    "🪝 Hooks: not installed (run 'donttouch hooks install' to install)\n",
+                ),
+            }
+            out.push_str(&format!(
+                "🛡️  Hook policy: {} ({})\n",
+                policy.name(),
+                describe_policy(policy)
+            ));
+        }
+    }
+
+    out.push_str("\nPatterns:\n");
+    for p in &config.protect.patterns() {
+        out.push_str(&format!("   {p}\n"));
+    }
+
+    if files.is_empty() {
+        out.push_str("\nNo files currently match the protected patterns.");
+    } else {
+        out.push_str("\nProtected files:\n");
+        for f in files {
+            let icon = if f.readonly {
+                "🔒 read-only"
+            } else {
+                "🔓 writable"
+            };
+            out.push_str(&format!("   {icon}  {}", f.path.display()));
+            if let Some(link) = &f.link {
+                match &link.target {
+                    Some(t) => out.push_str(&format!("  → {}", t.display())),
+                    None => out.push_str("  → (missing)"),
+                }
+                if let Some(reason) = link.blocked {
+                    out.push_str(&format!("  ⚠️  {reason}"));
+                }
+            }
+            out.push('\n');
+        }
+    }
+
+    State::Done { message: out }
+}
+
+/// Enable protection: set `enabled = true` and make the protected files and
+/// config read-only.
+fn do_lock(
+    files: &[ProtectedFile],
+    config: &ConfigFile,
+    root: &Path,
+    enabled: bool,
+    dry_run: bool,
+) -> State {
+    let mut out = String::new();
+    let mut plan = Plan::default();
+    let mut already = 0;
+    let mut refused = false;
+
+    let config_path = root.join(".donttouch.toml");
+    if !enabled {
+        match enabled_content(root, true) {
+            Ok(content) => plan.write(config_path.clone(), content),
+            Err(e) => return State::Error { message: e },
+        }
+    }
+
+    for f in files {
+        if f.readonly {
+            already += 1;
+        } else if let Err(e) = plan.set_protected(f, true) {
+            out.push_str(&format!("   ❌ {e}\n"));
+            refused = true;
+        }
+    }
+
+    // Also lock the config file itself
+    if is_file_readonly(&config_path) {
+        already += 1;
+    } else {
+        plan.set_readonly(&config_path, true);
+    }
+
+    // Refresh agent instructions after pattern changes
+    plan.extend(sync_agent_instructions(root, config, true).1);
+
+    if dry_run {
+        return State::Done {
+            message: out + &plan.preview(root),
+        };
+    }
+    let applied = plan.apply(root);
+    out.push_str(&applied.render(&plan.notes));
+    if !applied.ok() {
+        return State::Error { message: out };
+    }
+    let locked = applied.modes;
+
+    if locked > 0 {
+        out.push_str(&format!("\n✅ Locked {locked} file(s)."));
+    }
+    if already > 0 {
+        out.push_str(&format!("\n   ({already} already read-only)"));
+    }
+    if locked == 0 && already > 0 {
+        out.push_str("\n✅ All protected files are already read-only.");
+    }
+    if !enabled {
+        out.push_str("\n✅ Protection enabled.");
+    }
+
+    // Files the symlink rules kept unlocked fail 'check' too
+    if refused {
+        State::Error { message: out }
+    } else {
+        State::Done { message: out }
+    }
+}
+
+/// Disable protection: set `enabled = false` and make the protected files and
+/// config writable.
+fn do_unlock(files: &[ProtectedFile], root: &Path, enabled: bool, dry_run: bool) -> State {
+    let mut out = String::new();
+    let mut plan = Plan::default();
+    let mut refused = false;
+
+    for f in files {
+        if f.readonly {
+            if let Err(e) = plan.set_protected(f, false) {
+                out.push_str(&format!("   ❌ {e}\n"));
+                refused = true;
+            }
+        }
+    }
+    let config_path = root.join(".donttouch.toml");
+    plan.set_readonly(&config_path, false);
+    if enabled {
+        match enabled_content(root, false) {
+            Ok(content) => plan.write(config_path, content),
+            Err(e) => return State::Error { message: e },
+        }
+    }
+
+    if dry_run {
+        return State::Done {
+            message: out + &plan.preview(root),
+        };
+    }
+    let applied = plan.apply(root);
+    out.push_str(&applied.render(&plan.notes));
+    if !applied.ok() {
+        return State::Error { message: out };
+    }
+    let unlocked = applied.modes;
+
+    if unlocked > 0 {
+        out.push_str(&format!("\n✅ Unlocked {unlocked} file(s)."));
+    } else {
+        out.push_str("All files were already writable.");
+    }
+    if enabled {
+        out.push_str(
+            "\n🔓 Protection disabled.\n   ⚠️  Pushes are blocked until you run 'donttouch lock'.",
+        );
+    }
+
+    if refused {
+        State::Error { message: out }
+    } else {
+        State::Done { message: out }
+    }
+}
+
+fn do_check(files: &[ProtectedFile], config: &ConfigFile, root: &Path, context: &Context) -> State {
+    let mut issues = Vec::new();
+    let mut deferred = Vec::new();
+
+    // Check 1: permission violations (all contexts)
+    let writable: Vec<&ProtectedFile> = files.iter().filter(|f| !f.readonly).collect();
+    if !writable.is_empty() {
+        issues.push("Permission violations (files are writable):".to_string());
+        for f in &writable {
+            let rel = f
+                .path
+                .strip_prefix(root)
+                .unwrap_or(&f.path)
+                .to_string_lossy();
+            let item = match f.link.as_ref().and_then(|l| l.blocked) {
+                Some(reason) => format!("{} ({reason})", f.path.display()),
+                None => f.path.display().to_string(),
+            };
+            issues.push(config.protect.bullet("   ", item, &[&rel]));
+        }
+    }
+
+    // Check 2: staged file violations (git context only)
+    if let Context::Git {
+        manager, hooks_dir, ..
+    } = context
+    {
+        let patterns = files_to_patterns(root);
+        if !patterns.is_empty() {
+            let staged = get_staged_changes(root);
+            let staged_violations: Vec<&Change> =
+                staged.iter().filter(|c| c.touches(&patterns)).collect();
+            let trailer = config.approval.trailer.as_ref().filter(|_| {
+                !staged_violations.is_empty() && hook_runs(root, *manager, hooks_dir, "commit-msg")
+            });
+
+            // With approvals configured the commit message decides, and it
+            // doesn't exist yet: leave staged changes to the commit-msg hook
+            if let Some(key) = trailer {
+                deferred.push(format!(
+                    "\n⚠️  Staged changes to protected files need a '{key}:' trailer (checked at commit-msg):"
+                ));
+                for c in &staged_violations {
+                    deferred.push(config.protect.bullet("   ", c, &c.paths()));
+                }
+            } else if !staged_violations.is_empty() {
+                if !issues.is_empty() {
+                    issues.push(String::new());
+                }
+                issues.push(
+                    "Staged file violations (protected files in git staging area):".to_string(),
+                );
+                for c in &staged_violations {
+                    issues.push(config.protect.bullet("   ", c, &c.paths()));
+                }
+                if let Some(key) = &config.approval.trailer {
+                    issues.push(format!(
+                        "   (the commit-msg hook isn't installed, so a '{key}:' trailer can't \
+                         approve them — run 'donttouch hooks install')"
+                    ));
+                }
+            }
+        }
+    }
+
+    // Check 3: agent instructions (with [inject] enforce)
+    let lock_fixable = !issues.is_empty();
+    let mut stale_instructions = false;
+    if config.inject.enforce {
+        // Only files donttouch has injected into; other agent files are the user's business
+        let stale: Vec<String> = sync_agent_instructions(root, config, true)
+            .0
+            .into_iter()
+            .filter_map(|(path, outcome)| match outcome {
+                InjectOutcome::Updated => Some(format!("   • {path} (instructions are outdated)")),
+                InjectOutcome::Failed(e) => Some(format!("   • {path} ({e})")),
+                InjectOutcome::UpToDate | InjectOutcome::Injected | InjectOutcome::Created => None,
+            })
+            .collect();
+        if !stale.is_empty() {
+            if !issues.is_empty() {
+                issues.push(String::new());
+            }
+            issues.push("Agent instruction violations ([inject] enforce):".to_string());
+            issues.extend(stale);
+            stale_instructions = true;
+        }
+    }
+
+    if issues.is_empty() {
+        let mut out = String::from("✅ All protected files are read-only.");
+        for line in &deferred {
+            out.push('\n');
+            out.push_str(line);
+        }
+        State::Done { message: out }
+    } else {
+        let mut out = String::from("🚫 donttouch check failed!\n\n");
+        for line in &issues {
+            out.push_str(line);
+            out.push('\n');
+        }
+        if lock_fixable {
+            out.push_str("\nRun 'donttouch lock' to fix permission issues.");
+        }
+        if stale_instructions {
+            out.push_str("\nRun 'donttouch inject' to restore agent instructions.");
+        }
+        State::Error { message: out }
+    }
+}
+
+/// Shared check behind the commit-msg, pre-merge-commit, pre-rebase and
+/// post-rewrite hooks: inspect what the resulting commits change relative to
+/// their parents.
+fn do_check_hook(
+    hook: &str,
+    args: &[String],
+    config: &ConfigFile,
+    root: &Path,
+    context: &Context,
+) -> State {
+    if !context.is_git() {
+        return State::Error {
+            message: "🚫 check-hook requires a git repository.".into(),
+        };
+    }
+
+    let patterns = compile_patterns(&config.protected_patterns(root));
+    let approval = &config.approval;
+    let mut violations: Vec<(Option<String>, Vec<Change>)> = Vec::new();
+    let mut unapproved: Vec<Unapproved> = Vec::new();
+
+    match hook {
+        // The index is about to become a commit on HEAD (and MERGE_HEAD, for a merge).
+        // Re-checking at commit-msg catches files re-staged by other pre-commit hooks.
+        "commit-msg" | "pre-merge-commit" => {
+            let changes: Vec<Change> = get_staged_changes(root)
+                .into_iter()
+                .filter(|c| c.touches(&patterns))
+                .collect();
+            if changes.is_empty() {
+                // nothing to approve
+            } else if let (Some(key), "pre-merge-commit") = (&approval.trailer, hook) {
+                // git runs commit-msg for the merge commit too, and its message decides
+                return State::Done {
+                    message: format!(
+                        "⚠️  donttouch pre-merge-commit: protected files changed; \
+                         the merge message needs a '{key}:' trailer."
+                    ),
+                };
+            } else {
+                match message_approval(root, args.first().map(String::as_str), approval) {
+                    Ok(approver) => {
+                        let mut message = format!(
+                            "✅ donttouch {hook}: protected changes approved by {approver}."
+                        );
+                        if !approval.signers.is_empty() {
+                            message.push_str(
+                                "\n   The signature is verified by pre-push and 'donttouch ci'.",
+                            );
+                        }
+                        return State::Done { message };
+                    }
+                    Err(reason) => unapproved.push(Unapproved {
+                        sha: None,
+                        changes,
+                        reason,
+                    }),
+                }
+            }
+        }
+        // pre-rebase <upstream> [<branch>]: the commits about to be replayed
+        // (the pre-commit framework passes these as environment variables instead)
+        "pre-rebase" => {
+            let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+            let Some(upstream) = args
+                .first()
+                .cloned()
+                .or_else(|| env("PRE_COMMIT_PRE_REBASE_UPSTREAM"))
+            else {
+                return State::Error {
+                    message: "🚫 pre-rebase: missing upstream argument.".into(),
+                };
+            };
+            let branch = args
+                .get(1)
+                .cloned()
+                .or_else(|| env("PRE_COMMIT_PRE_REBASE_BRANCH"))
+                .unwrap_or_else(|| "HEAD".into());
+            let range = format!("{upstream}..{branch}");
+            // Judge by the current patterns only: commits made while the project
+            // was unlocked were legitimate under the rules of the time
+            violations = protected_commits(root, &[range.as_str()], &patterns)
+                .into_iter()
+                .filter_map(|(sha, changes)| {
+                    let changes: Vec<Change> = changes
+                        .into_iter()
+                        .filter(|c| c.touches(&patterns))
+                        .collect();
+                    (!changes.is_empty()).then_some((sha, changes))
+                })
+                .collect();
+        }
+        // post-rewrite <amend|rebase>, with "<old> <new>" pairs on stdin
+        "post-rewrite" => {
+            let mut rewritten = Vec::new();
+            for line in io::stdin().lines().map_while(Result::ok) {
+                if let Some(new) = line.split_whitespace().nth(1) {
+                    rewritten.push(new.to_string());
+                }
+            }
+            for sha in rewritten {
+                let changes: Vec<Change> = commit_changes(root, &sha)
+                    .into_iter()
+                    .filter(|c| c.touches(&patterns))
+                    .collect();
+                if !changes.is_empty() {
+                    violations.push((Some(sha), changes));
+                }
+            }
+        }
+        other => {
+            return State::Error {
+                message: format!("🚫 Unknown hook '{other}'."),
+            };
+        }
+    }
+
+    let (approved, rejected) = review_commits(root, violations, approval);
+    unapproved.extend(rejected);
+    if unapproved.is_empty() {
+        let mut out = format!("✅ donttouch {hook}: no unapproved changes to protected files.");
+        for line in &approved {
+            out.push_str(&format!("\n   {line}"));
+        }
+        return State::Done { message: out };
+    }
+
+    // Without a trailer these commits had no way to be approved: they were made
+    // while the project was unlocked, so warn instead of stopping the rebase
+    if hook == "pre-rebase" && approval.trailer.is_none() {
+        let mut out =
+            String::from("⚠️  donttouch pre-rebase: these commits change protected files:\n");
+        render_unapproved(&mut out, root, &config.protect, &unapproved);
+        out.push_str("\nRebasing anyway — review them before pushing.");
+        return State::Done { message: out };
+    }
+
+    let mut out = format!("🚫 donttouch {hook} check failed!\n\nProtected files changed:\n");
+    render_unapproved(&mut out, root, &config.protect, &unapproved);
+    if hook == "pre-rebase" {
+        out.push_str(&format!(
+            "\nAdd a '{}:' trailer to these commits, or rebase with 'git rebase --no-verify'.",
+            approval.trailer.as_deref().unwrap_or_default()
+        ));
+    } else if hook == "post-rewrite" {
+        out.push_str("\nThe rewrite already happened — review these commits before pushing.");
+    } else if let Some(key) = &approval.trailer {
+        out.push_str(&format!(
+            "\nIf this change is intended, add a '{key}:' trailer to the commit message."
+        ));
+    } else {
+        out.push_str(
+            "\nIf this change is intended, run 'donttouch unlock <path>' from outside the project.",
+        );
+    }
+    State::Error { message: out }
+}
+
+fn do_check_push(enabled: bool, config: &ConfigFile, root: &Path, context: &Context) -> State {
+    if !context.is_git() {
+        return State::Error {
+            message: "🚫 check-push requires a git repository.".into(),
+        };
+    }
+
+    if !enabled {
+        return State::Error {
+            message: "🚫 donttouch: push blocked! Protection is currently disabled.\n\n\
+                      You must re-enable protection before pushing:\n\
+                      \n   donttouch lock\n\n\
+                      This ensures protected files are checked before code leaves your machine."
+                .into(),
+        };
+    }
+
+    // git passes one "<local ref> <local sha> <remote ref> <remote sha>" line per
+    // ref on stdin. Don't wait for input when run by hand from a terminal.
+    let mut updates: Vec<String> = if io::stdin().is_terminal() {
+        Vec::new()
+    } else {
+        io::stdin().lines().map_while(Result::ok).collect()
+    };
+
+    // The pre-commit framework consumes stdin and exports the range instead
+    if updates.is_empty() {
+        if let (Ok(from), Ok(to)) = (
+            std::env::var("PRE_COMMIT_FROM_REF"),
+            std::env::var("PRE_COMMIT_TO_REF"),
+        ) {
+            updates.push(format!("- {to} - {from}"));
+        }
+    }
+
+    let patterns = compile_patterns(&config.protected_patterns(root));
+    let mut violations = Vec::new();
+    for line in &updates {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, local_sha, _, remote_sha] = fields[..] else {
+            continue;
+        };
+        let range = push_range(root, local_sha, remote_sha);
+        let args: Vec<&str> = range.iter().map(String::as_str).collect();
+        if !args.is_empty() {
+            // The remote's config counts too; the pushed branch can't relax it
+            let mut patterns = patterns.clone();
+            patterns.extend(committed_patterns(root, remote_sha));
+            violations.extend(protected_commits(root, &args, &patterns));
+        }
+    }
+
+    let (approved, unapproved) = review_commits(root, violations, &config.approval);
+    if unapproved.is_empty() {
+        let mut out = String::from("✅ donttouch is enabled. Push allowed.");
+        for line in &approved {
+            out.push_str(&format!("\n   {line}"));
+        }
+        return State::Done { message: out };
+    }
+
+    let mut out =
+        String::from("🚫 donttouch: push blocked! These commits change protected files:\n\n");
+    render_unapproved(&mut out, root, &config.protect, &unapproved);
+    out.push_str(
+        "\nThey were likely committed with --no-verify or while protection was disabled.\n",
+    );
+    match &config.approval.trailer {
+        Some(key) => out.push_str(&format!(
+            "Reword them with a '{key}:' trailer{} if the change is intended.",
+            if config.approval.signers.is_empty() {
+                ""
+            } else {
+                " and sign them with an allowed key"
+            }
+        )),
+        None => out.push_str("Rewrite them, or push with --no-verify if the change is intended."),
+    }
+    State::Error { message: out }
+}
+
+/// `git rev-list` arguments selecting the commits a ref update would push.
+fn push_range(root: &Path, local_sha: &str, remote_sha: &str) -> Vec<String> {
+    let is_zero = |sha: &str| sha.chars().all(|c| c == '0');
+
+    // Deleting a remote ref pushes no commits
+    if is_zero(local_sha) {
+        return Vec::new();
+    }
+
+    // Known remote tip: the commits it already has are not pushed again
+    let known = !is_zero(remote_sha)
+        && git_output(
+            root,
+            &["cat-file", "-e", &format!("{remote_sha}^{{commit}}")],
+        )
+        .is_some();
+    // Never re-check commits already on any remote, including when pushing
+    // to a new remote or fork that has no remote-tracking refs yet
+    let mut range = vec![
+        local_sha.to_string(),
+        "--not".to_string(),
+        "--remotes".to_string(),
+    ];
+    if known {
+        range.push(remote_sha.to_string());
+    }
+    range
+}
+
+/// Range check for CI: every commit in `base..head` that touches protected files
+/// must be approved. Approval rules come from the base's committed config, so a
+/// branch can't approve itself by editing `[approval]`.
+fn do_ci(
+    enabled: bool,
+    base: &str,
+    head: &str,
+    working_config: &ConfigFile,
+    root: &Path,
+    context: &Context,
+) -> State {
+    if !context.is_git() {
+        return State::Error {
+            message: "🚫 ci requires a git repository.".into(),
+        };
+    }
+    if !enabled {
+        return State::Error {
+            message: "🚫 donttouch ci: protection is disabled in .donttouch.toml.".into(),
+        };
+    }
+    for rev in [base, head] {
+        if git_output(
+            root,
+            &["rev-parse", "--verify", "-q", &format!("{rev}^{{commit}}")],
+        )
+        .is_none()
+        {
+            return State::Error {
+                message: format!("🚫 Unknown revision '{rev}'."),
+            };
+        }
+    }
+
+    // The branch under review must not decide its own rules: read them from the base
+    let config = committed_config(root, base);
+    let config = config.as_ref().unwrap_or(working_config);
+    let approval = &config.approval;
+    let patterns = compile_patterns(&config.protected_patterns(root));
+    let range = format!("{base}..{head}");
+    let commits = protected_commits(root, &[range.as_str()], &patterns);
+    let (approved, unapproved) = review_commits(root, commits, approval);
+
+    if unapproved.is_empty() {
+        let mut out =
+            format!("✅ donttouch ci: no unapproved changes to protected files in {range}.");
+        for line in &approved {
+            out.push_str(&format!("\n   {line}"));
+        }
+        return State::Done { message: out };
+    }
+
+    let mut out = format!("🚫 donttouch ci: commits in {range} change protected files:\n\n");
+    render_unapproved(&mut out, root, &config.protect, &unapproved);
+    match &approval.trailer {
+        Some(key) => out.push_str(&format!(
+            "\nAdd a '{key}:' trailer to these commits{} to approve them.",
+            if approval.signers.is_empty() {
+                ""
+            } else {
+                " and sign them with an allowed key"
+            }
+        )),
+        None => out.push_str("\nConfigure [approval] trailer to allow approved changes."),
+    }
+    State::Error { message: out }
+}
+
+fn do_remove(
+    files: &[ProtectedFile],
+    config: &ConfigFile,
+    root: &Path,
+    context: &Context,
+    dry_run: bool,
+) -> State {
+    let mut plan = Plan::default();
+
+    for f in files {
+        if f.readonly {
+            if let Err(e) = plan.set_protected(f, false) {
+                plan.notes.push(format!("   ⚠️  Left read-only: {e}"));
+            }
+        }
+    }
+
+    // Delete config
+    plan.delete(root.join(".donttouch.toml"));
+
+    // Clean up git hooks if applicable
+    if let Context::Git {
+        manager, hooks_dir, ..
+    } = context
+    {
+        plan_remove_hooks(&mut plan, root, *manager, hooks_dir);
+    }
+
+    // Clean up agent instructions and exported files
+    plan_remove_agent_instructions(&mut plan, root, config);
+    plan_remove_codeowners_block(&mut plan, root);
+    plan_remove_ignore_blocks(&mut plan, root);
+
+    // Save everything about to change first, so 'restore' can undo it
+    let backup = Backup::of(&plan, root);
+    if dry_run {
+        plan.notes.push(format!(
+            "💾 Would back up {} file(s) to {BACKUP_DIR}/",
+            backup.files.len()
+        ));
+        return State::Done {
+            message: plan.preview(root),
+        };
+    }
+    let saved = match backup.save(root) {
+        Ok(path) => path,
+        Err(e) => {
+            return State::Error {
+                message: format!("❌ Could not back up before removing: {e}\nNothing was removed."),
+            }
+        }
+    };
+
+    let applied = plan.apply(root);
+    let mut out = applied.render(&plan.notes);
+    if !applied.ok() {
+        return State::Error { message: out };
+    }
+    if applied.modes > 0 {
+        out.push_str(&format!("\n   Unlocked {} file(s).", applied.modes));
+    }
+    out.push_str(&format!(
+        "\n💾 Backed up {} file(s) to {}\n   Undo with: donttouch restore {} {}",
+        backup.files.len(),
+        saved.strip_prefix(root).unwrap_or(&saved).display(),
+        root.display(),
+        saved.display()
+    ));
+    out.push_str("\n✅ donttouch removed.");
+
+    State::Done { message: out }
+}
+
+fn do_restore(target: &str, backup: &str, dry_run: bool) -> State {
+    let root = match outside_target(target) {
+        Ok(p) => p,
+        Err(e) => return State::Error { message: e },
+    };
+    if !dry_run {
+        if let Err(e) = acquire_process_lock(&root) {
+            return State::Error { message: e };
+        }
+    }
+    if root.join(".donttouch.toml").exists() {
+        return State::Error {
+            message: format!(
+                "⚠️  {} already has a .donttouch.toml. Run 'donttouch remove' first to replace it.",
+                root.display()
+            ),
+        };
+    }
+
+    let parsed: Backup = match std::fs::read_to_string(backup)
+        .map_err(|e| e.to_string())
+        .and_then(|c| serde_json::from_str(&c).map_err(|e| e.to_string()))
+    {
+        Ok(b) => b,
+        Err(e) => {
+            return State::Error {
+                message: format!("Invalid backup {backup}: {e}"),
+            }
+        }
+    };
+    if parsed.version != BACKUP_VERSION {
+        return State::Error {
+            message: format!(
+                "Backup {backup} has format version {}; this donttouch reads version {BACKUP_VERSION}.",
+                parsed.version
+            ),
+        };
+    }
+
+    let plan = match parsed.plan(&root) {
+        Ok(p) => p,
+        Err(e) => return State::Error { message: e },
+    };
+    if dry_run {
+        return State::Done {
+            message: plan.preview(&root),
+        };
+    }
+
+    let applied = plan.apply(&root);
+    let out = applied.render(&plan.notes);
+    if applied.ok() {
+        State::Done {
+            message: format!("{out}\n✅ Restored donttouch from {backup}."),
+        }
+    } else {
+        State::Error {
+            message: format!("{out}\n⚠️  Restore incomplete: see the errors above."),
+        }
+    }
+}
+
+fn do_why(file: &str, config: &ConfigFile) -> State {
+    let matching: Vec<(usize, &PatternEntry)> = config
+        .protect
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| {
+            Pattern::new(e.pattern())
+                .map(|pat| pat.matches(file))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    if matching.is_empty() {
+        State::Done {
+            message: format!("{file} is not protected by any pattern."),
+        }
+    } else {
+        // Read config to find the line number of the patterns array
+        let config_lines: Vec<String> = std::fs::read_to_string(".donttouch.toml")
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect();
+
+        let mut out = format!("{file} is protected by:\n");
+        for (idx, entry) in &matching {
+            let p = entry.pattern();
+            // Find the line number of this pattern in the config file
+            let line_num = config_lines
+                .iter()
+                .position(|l| {
+                    let trimmed = l.trim().trim_matches(',');
+                    trimmed == format!("\"{p}\"")
+                        || trimmed == format!("'{p}'")
+                        || trimmed.starts_with(&format!("{{ pattern = \"{p}\""))
+                })
+                .map(|n| n + 1); // 1-indexed
+
+            if let Some(ln) = line_num {
+                out.push_str(&format!("   • {p}  (.donttouch.toml:{ln})\n"));
+            } else {
+                out.push_str(&format!("   • {p}  (pattern #{})\n", idx + 1));
+            }
+            if let Some(note) = entry.note() {
+                out.push_str(&format!("     {note}\n"));
+            }
+        }
+        State::Done { message: out }
+    }
+}
+
+fn do_hooks(action: &HooksAction, config: &ConfigFile, root: &Path, context: &Context) -> State {
+    let Context::Git {
+        manager, hooks_dir, ..
+    } = context
+    else {
+        return State::Error {
+            message: "📁 Not a git repository — there are no hooks to manage.".into(),
+        };
+    };
+    let policy = config.protect.hook_policy;
+
+    match action {
+        HooksAction::Install => match install_hooks(root, *manager, hooks_dir, false, policy) {
+            Ok(()) => State::Done {
+                message: "✅ Git hooks installed.".into(),
+            },
+            Err(e) => State::Error {
+                message: format!("❌ {e}"),
+            },
+        },
+        HooksAction::Upgrade => match install_hooks(root, *manager, hooks_dir, true, policy) {
+            Ok(()) => State::Done {
+                message: "✅ Git hooks up to date.".into(),
+            },
+            Err(e) => State::Error {
+                message: format!("❌ {e}"),
+            },
+        },
+        HooksAction::Uninstall { .. } => remove_hooks(root, *manager, hooks_dir),
+        HooksAction::Status => {
+            let mut out = String::new();
+            let mut healthy = true;
+            match manager.config_file(root) {
+                Some(config) => {
+                    let state = manager_state(root, *manager, policy);
+                    healthy = state == HookState::Current;
+                    out.push_str(&format!(
+                        "🪝 Hooks ({}, via {}):\n",
+                        config.display(),
+                        manager.name()
+                    ));
+                    out.push_str(&format!("   {}\n", describe_hook_state(&state)));
+                }
+                None => {
+                    out.push_str(&format!("🪝 Hooks ({}):\n", hooks_dir.display()));
+                    for (hook_name, _) in HOOKS {
+                        let state = hook_file_state(&hooks_dir.join(hook_name), policy);
+                        healthy &= state == HookState::Current;
+                        out.push_str(&format!(
+                            "   {hook_name:<17} {}\n",
+                            describe_hook_state(&state)
+                        ));
+                    }
+                }
+            }
+            out.push_str(&format!(
+                "\nCurrent hook version: v{HOOK_VERSION}, hook_policy = {}",
+                policy.name()
+            ));
+            if healthy {
+                State::Done { message: out }
+            } else {
+                out.push_str("\n👉 Run 'donttouch hooks install' or 'donttouch hooks upgrade'.");
+                State::Error { message: out }
+            }
+        }
+    }
+}
+
+/// What hooks do under `policy` when donttouch can't be found.
+fn describe_policy(policy: HookPolicy) -> &'static str {
+    match policy {
+        HookPolicy::FailClosed => "hooks block when donttouch is missing",
+        HookPolicy::Warn => "hooks warn and continue when donttouch is missing",
+        HookPolicy::Skip => "hooks pass silently when donttouch is missing",
+    }
+}
+
+fn describe_hook_state(state: &HookState) -> String {
+    match state {
+        HookState::Current => format!("✅ up to date (v{HOOK_VERSION})"),
+        HookState::Outdated(what) => format!("⚠️  outdated: {what}"),
+        HookState::Missing => "❌ not installed".into(),
+    }
+}
+
+// =============================================================================
+// Doctor
+// =============================================================================
+
+/// Findings collected by `donttouch doctor`.
+#[derive(Default)]
+struct Diagnosis {
+    lines: Vec<String>,
+    warnings: usize,
+    errors: usize,
+}
+
+impl Diagnosis {
+    fn section(&mut self, title: &str) {
+        if !self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.lines.push(format!("{title}:"));
+    }
+
+    fn ok(&mut self, msg: impl AsRef<str>) {
+        self.lines.push(format!("   ✅ {}", msg.as_ref()));
+    }
+
+    fn warn(&mut self, msg: impl AsRef<str>) {
+        self.lines.push(format!("   ⚠️  {}", msg.as_ref()));
+        self.warnings += 1;
+    }
+
+    fn error(&mut self, msg: impl AsRef<str>) {
+        self.lines.push(format!("   ❌ {}", msg.as_ref()));
+        self.errors += 1;
+    }
+}
+
+fn do_doctor(config: &ConfigFile, root: &Path, context: &Context) -> State {
+    let mut d = Diagnosis::default();
+
+    diagnose_patterns(&mut d, config, root);
+    if let Context::Git {
+        manager, hooks_dir, ..
+    } = context
+    {
+        diagnose_hooks(
+            &mut d,
+            root,
+            *manager,
+            hooks_dir,
+            config.protect.hook_policy,
+        );
+    }
+    diagnose_agent_files(&mut d, config, root);
+
+    let mut out = d.lines.join("\n");
+    out.push_str("\n\n");
+    if d.errors > 0 {
+        out.push_str(&format!(
+            "🚫 {} error(s), {} warning(s).",
+            d.errors, d.warnings
+        ));
+        State::Error { message: out }
+    } else if d.warnings > 0 {
+        out.push_str(&format!("⚠️  No errors, {} warning(s).", d.warnings));
+        State::Warning { message: out }
+    } else {
+        out.push_str("✅ Everything looks good.");
+        State::Done { message: out }
+    }
+}
+
+fn diagnose_patterns(d: &mut Diagnosis, config: &ConfigFile, root: &Path) {
+    let raw = &config.protect.patterns();
+    d.section("Config");
+
+    if raw.is_empty() {
+        d.warn("No patterns configured — nothing is protected.");
+        return;
+    }
+
+    let mut compiled: Vec<(&String, Pattern)> = Vec::new();
+    for (i, p) in raw.iter().enumerate() {
+        if raw[..i].contains(p) {
+            d.warn(format!("'{p}' is listed more than once."));
+            continue;
+        }
+        match Pattern::new(p) {
+            Ok(pat) => compiled.push((p, pat)),
+            Err(e) => d.error(format!("'{p}' is not a valid glob: {e}")),
+        }
+    }
+
+    let all: Vec<Pattern> = compiled.iter().map(|(_, p)| p.clone()).collect();
+    let skip = SkipRules::new(&config.discover.skip);
+    let rel_paths: Vec<String> = discover_files(root, &all, &config.discover)
+        .iter()
+        .map(|f| {
+            f.path
+                .strip_prefix(root)
+                .unwrap_or(&f.path)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+
+    if config.discover.gitignore || config.discover.tracked_only {
+        let everything = DiscoverSection {
+            skip: config.discover.skip.clone(),
+            gitignore: false,
+            tracked_only: false,
+            cache: false,
+            ..DiscoverSection::default()
+        };
+        for f in discover_files(root, &all, &everything) {
+            let rel = f
+                .path
+                .strip_prefix(root)
+                .unwrap_or(&f.path)
+                .to_string_lossy();
+            if !rel_paths.iter().any(|r| *r == rel) {
+                d.warn(format!(
+                    "'{rel}' matches a pattern but is hidden by [discover] gitignore/tracked_only — it is not protected."
+                ));
+            }
+        }
+    }
+
+    let matches: Vec<Vec<usize>> = compiled
+        .iter()
+        .map(|(_, pat)| {
+            rel_paths
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| pat.matches(f))
+                .map(|(i, _)| i)
+                .collect()
+        })
+        .collect();
+
+    for (i, (raw_pat, _)) in compiled.iter().enumerate() {
+        if let Some(dir) = skipped_prefix(raw_pat, &skip) {
+            d.warn(format!(
+                "'{raw_pat}' targets '{dir}/', which discovery skips ([discover] skip)."
+            ));
+        } else if matches[i].is_empty() {
+            d.warn(format!("'{raw_pat}' matches no files."));
+        } else if let Some(j) = (0..compiled.len()).find(|&j| {
+            j != i
+                && matches[i].iter().all(|f| matches[j].contains(f))
+                && (matches[j].len() > matches[i].len() || j < i)
+        }) {
+            d.warn(format!(
+                "'{raw_pat}' is redundant — every file it matches is covered by '{}'.",
+                compiled[j].0
+            ));
+        } else {
+            d.ok(format!("'{raw_pat}' matches {} file(s)", matches[i].len()));
+        }
+    }
+}
+
+/// If one of a pattern's literal leading directories is skipped by discovery, return it.
+fn skipped_prefix<'a>(pattern: &'a str, skip: &SkipRules) -> Option<&'a str> {
+    let mut end = 0;
+    let mut parts = pattern.split('/').peekable();
+    while let Some(part) = parts.next() {
+        // The last component is the file name, not a directory
+        if parts.peek().is_none() || part.contains(['*', '?', '[']) {
+            break;
+        }
+        end += part.len();
+        if skip.matches(&pattern[..end], part) {
+            return Some(&pattern[..end]);
+        }
+        end += 1;
+    }
+    None
+}
+
+fn diagnose_hooks(
+    d: &mut Diagnosis,
+    root: &Path,
+    manager: HookManager,
+    dir: &Path,
+    policy: HookPolicy,
+) {
+    d.section("Git hooks");
+
+    let summary = format!(
+        "hook_policy = {}: {}",
+        policy.name(),
+        describe_policy(policy)
+    );
+    if policy == HookPolicy::Skip {
+        d.warn(format!("{summary}."));
+    } else {
+        d.ok(summary);
+    }
+
+    let mut installed = 0;
+    match manager.config_file(root) {
+        Some(config) => {
+            let shown = config.strip_prefix(root).unwrap_or(&config).display();
+            if let HookState::Outdated(what) = manager_state(root, manager, policy) {
+                d.warn(format!(
+                    "{shown} is outdated: {what}. Run 'donttouch hooks upgrade'."
+                ));
+            }
+            for (hook_name, donttouch_cmd) in HOOKS {
+                if manager == HookManager::PreCommit && PRE_COMMIT_UNSUPPORTED.contains(hook_name) {
+                    continue;
+                }
+                if manager_has_hook(root, manager, hook_name, donttouch_cmd) {
+                    installed += 1;
+                    d.ok(format!("{shown} runs donttouch for {hook_name}"));
+                } else {
+                    d.warn(format!("{shown} has no donttouch {hook_name} entry."));
+                }
+            }
+        }
+        None => {
+            for (hook_name, donttouch_cmd) in HOOKS {
+                let path = dir.join(hook_name);
+                let shown = path.strip_prefix(root).unwrap_or(&path).display();
+                if !path.exists() {
+                    d.warn(format!("{shown} is not installed."));
+                    continue;
+                }
+                installed += 1;
+                if !hook_contains(&path, donttouch_cmd) {
+                    d.error(format!("{shown} does not run '{donttouch_cmd}'."));
+                } else if !is_executable(&path) {
+                    d.error(format!("{shown} is not executable, so git will skip it."));
+                } else if let HookState::Outdated(what) = hook_file_state(&path, policy) {
+                    d.warn(format!(
+                        "{shown} is outdated: {what}. Run 'donttouch hooks upgrade'."
+                    ));
+                } else {
+                    d.ok(format!("{shown} runs '{donttouch_cmd}'"));
+                }
+            }
+        }
+    }
+
+    match find_on_path("donttouch") {
+        Some(p) => d.ok(format!("donttouch found on PATH at {}", p.display())),
+        None if installed > 0 && policy == HookPolicy::Skip => {
+            d.error("donttouch is not on PATH — hooks skip their checks when it cannot be found.")
+        }
+        None if installed > 0 => d.warn(format!(
+            "donttouch is not on PATH — hooks fall back to the binary recorded at install time, then {}.",
+            if policy == HookPolicy::FailClosed {
+                "block"
+            } else {
+                "warn"
+            }
+        )),
+        None => d.warn("donttouch is not on PATH."),
+    }
+}
+
+fn diagnose_agent_files(d: &mut Diagnosis, config: &ConfigFile, root: &Path) {
+    d.section("Agent instructions");
+
+    let (outcomes, _) = sync_agent_instructions(root, config, true);
+
+    let mut seen = 0;
+    for file in agent_files(root, config) {
+        let path = root.join(&file.path);
+        if !path.is_file() {
+            continue;
+        }
+        seen += 1;
+        let outcome = outcomes
+            .iter()
+            .find(|(p, _)| *p == file.path)
+            .map(|(_, o)| o);
+        if let Some(InjectOutcome::Failed(e)) = outcome {
+            d.warn(format!("{} can't be updated: {e}", file.path));
+        } else if hook_contains(&path, MARKER) {
+            if matches!(outcome, Some(InjectOutcome::Updated)) {
+                d.warn(format!(
+                    "{} has outdated instructions (run 'donttouch inject --update').",
+                    file.path
+                ));
+            } else {
+                d.ok(format!("{} has instructions", file.path));
+            }
+        } else {
+            d.warn(format!(
+                "{} is missing donttouch instructions (run 'donttouch inject').",
+                file.path
+            ));
+        }
+    }
+
+    if seen == 0 {
+        d.warn("No agent config files found (run 'donttouch inject').");
+    }
+}
+
+/// Locate an executable on PATH the way a shell hook would.
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let path_var = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_var)
+        .flat_map(|dir| {
+            let candidate = dir.join(name);
+            let exe = dir.join(format!("{name}.exe"));
+            [candidate, exe]
+        })
+        .find(|p| p.is_file() && is_executable(p))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// =============================================================================
+// Outside-Directory Check
+// =============================================================================
+
+fn assert_outside(target: &str) -> Result<PathBuf, String> {
+    let canonical_target = outside_target(target)?;
+
+    if !canonical_target.join(".donttouch.toml").exists() {
+        return Err(format!(
+            "No .donttouch.toml found in '{}'. Is this the right directory?",
+            canonical_target.display()
+        ));
+    }
+
+    Ok(canonical_target)
+}
+
+/// Resolve `target`, refusing if the current directory is inside it.
+fn outside_target(target: &str) -> Result<PathBuf, String> {
+    let canonical_target = std::fs::canonicalize(target)
+        .map_err(|e| format!("Cannot resolve target path '{target}': {e}"))?;
+
+    let canonical_cwd = std::env::current_dir()
+        .and_then(std::fs::canonicalize)
+        .map_err(|e| format!("Cannot resolve current directory: {e}"))?;
+
+    if canonical_cwd.starts_with(&canonical_target) {
+        return Err(format!(
+            "🚫 This command must be run from OUTSIDE the target directory.\n\n\
+             Current directory: {}\n\
+             Target directory:  {}\n\n\
+             This restriction prevents AI coding agents from disabling protection\n\
+             while working inside the project.\n\n\
+             Try: cd {} && donttouch unlock {}",
+            canonical_cwd.display(),
+            canonical_target.display(),
+            canonical_target
+                .parent()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "/tmp".into()),
+            canonical_target.display(),
+        ));
+    }
+
+    Ok(canonical_target)
+}
+
+// =============================================================================
+// Process Lock
+// =============================================================================
+
+/// How long a command waits for another donttouch to finish.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The held lock. Kept until the process exits, so it covers every state a
+/// command passes through.
+static PROCESS_LOCK: OnceLock<std::fs::File> = OnceLock::new();
+
+/// Where the lock for `root` lives: the git dir, or outside git the temp dir
+/// (so the project itself never gets a stray file). Doesn't depend on
+/// `--ignoregit`, so every donttouch working on the project agrees on it.
+fn lock_path(root: &Path) -> PathBuf {
+    if root.join(".git").exists() {
+        if let Some(path) = git_path(root, "donttouch.lock") {
+            return path;
+        }
+    }
+    use std::hash::{Hash, Hasher};
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    std::fs::canonicalize(root)
+        .unwrap_or_else(|_| root.to_path_buf())
+        .hash(&mut h);
+    std::env::temp_dir().join(format!("donttouch-{:016x}.lock", h.finish()))
+}
+
+/// Take the project's advisory lock, waiting up to `LOCK_TIMEOUT` for another
+/// donttouch to release it.
+fn acquire_process_lock(root: &Path) -> Result<(), String> {
+    if PROCESS_LOCK.get().is_some() {
+        return Ok(());
+    }
+    let path = lock_path(root);
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        // A `*.lock` pattern may have made it read-only; locking doesn't need write access
+        .or_else(|_| std::fs::File::open(&path))
+        .map_err(|e| format!("Cannot open lock file {}: {e}", path.display()))?;
+    wait_for_lock(&file, &path, false)?;
+    let _ = PROCESS_LOCK.set(file);
+    Ok(())
+}
+
+/// Share the project's lock so a command reading file states doesn't see a
+/// half-applied change. Drop the file to release it. None when there is no
+/// lock file to share: nothing has ever held it.
+fn share_process_lock(root: &Path) -> Result<Option<std::fs::File>, String> {
+    let path = lock_path(root);
+    let Ok(file) = std::fs::File::open(&path) else {
+        return Ok(None);
+    };
+    wait_for_lock(&file, &path, true)?;
+    Ok(Some(file))
+}
+
+fn wait_for_lock(file: &std::fs::File, path: &Path, shared: bool) -> Result<(), String> {
+    let start = Instant::now();
+    loop {
+        let attempt = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+        match attempt {
+            Ok(()) => return Ok(()),
+            Err(std::fs::TryLockError::WouldBlock) if start.elapsed() < LOCK_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(std::fs::TryLockError::WouldBlock) => {
+                return Err(format!(
+                    "🔒 Another donttouch is running in this project.\n   \
+                     Waited {}s for {}; try again once it finishes.",
+                    LOCK_TIMEOUT.as_secs(),
+                    path.display()
+                ));
+            }
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(format!("Cannot lock {}: {e}", path.display()));
+            }
+        }
+    }
+}
+
+// =============================================================================
+// Git Helpers
+// =============================================================================
+
+/// A path-level change reported by `git diff --name-status`.
+struct Change {
+    /// Status letter: A, C, D, M, R, T or U
+    kind: char,
+    /// Source path of a rename or copy
+    from: Option<String>,
+    path: String,
+}
+
+impl Change {
+    /// Whether the change touches a protected path, on either side of a rename or copy.
+    fn touches(&self, patterns: &[Pattern]) -> bool {
+        patterns
+            .iter()
+            .any(|p| p.matches(&self.path) || self.from.as_deref().is_some_and(|f| p.matches(f)))
+    }
+
+    /// The path, and the source of a rename or copy.
+    fn paths(&self) -> Vec<&str> {
+        std::iter::once(self.path.as_str())
+            .chain(self.from.as_deref())
+            .collect()
+    }
+
+    fn describe_kind(&self) -> &'static str {
+        match self.kind {
+            'A' => "added",
+            'C' => "copied",
+            'D' => "deleted",
+            'M' => "modified",
+            'R' => "renamed",
+            'T' => "type changed",
+            'U' => "unmerged",
+            _ => "changed",
+        }
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.from {
+            Some(from) => write!(f, "{from} → {} ({})", self.path, self.describe_kind()),
+            None => write!(f, "{} ({})", self.path, self.describe_kind()),
+        }
+    }
+}
+
+/// Parse `git diff --name-status -z` output.
+fn parse_name_status(raw: &[u8]) -> Vec<Change> {
+    let text = String::from_utf8_lossy(raw);
+    let mut fields = text.split('\0').filter(|f| !f.is_empty());
+    let mut changes = Vec::new();
+
+    while let Some(status) = fields.next() {
+        let Some(kind) = status.chars().next() else {
+            continue;
+        };
+        let Some(first) = fields.next() else {
+            break;
+        };
+        if kind == 'R' || kind == 'C' {
+            let Some(second) = fields.next() else {
+                break;
+            };
+            changes.push(Change {
+                kind,
+                from: Some(first.to_string()),
+                path: second.to_string(),
+            });
+        } else {
+            changes.push(Change {
+                kind,
+                from: None,
+                path: first.to_string(),
+            });
+        }
+    }
+    changes
+}
+
+/// Staged changes, with rename/copy detection so both sides can be checked.
+fn get_staged_changes(root: &Path) -> Vec<Change> {
+    const DIFF: [&str; 7] = [
+        "diff",
+        "--cached",
+        "--name-status",
+        "-z",
+        "-M",
+        "-C",
+        "--diff-filter=ACDMRTU",
+    ];
+    let Some(staged) = git_output(root, &DIFF) else {
+        return Vec::new();
+    };
+    // During a merge the index only changes a path if it differs from every parent
+    let merge_heads = git_path(root, "MERGE_HEAD")
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .unwrap_or_default();
+    let mut diffs = vec![parse_name_status(&staged)];
+    for sha in merge_heads.split_whitespace() {
+        let mut args = DIFF.to_vec();
+        args.push(sha);
+        diffs.push(
+            git_output(root, &args)
+                .map(|o| parse_name_status(&o))
+                .unwrap_or_default(),
+        );
+    }
+    changed_from_every_parent(diffs)
+}
+
+/// Changes from the first diff whose path also changed in every other diff.
+/// For a merge, with one diff per parent, these are the paths the merge
+/// itself introduces rather than brings in from one side.
+fn changed_from_every_parent(mut diffs: Vec<Vec<Change>>) -> Vec<Change> {
+    if diffs.is_empty() {
+        return Vec::new();
+    }
+    let first = diffs.remove(0);
+    first
+        .into_iter()
+        .filter(|c| {
+            diffs.iter().all(|d| {
+                d.iter()
+                    .any(|o| o.paths().iter().any(|p| c.paths().contains(p)))
+            })
+        })
+        .collect()
+}
+
+/// Files tracked by git, relative to `root`. None outside a git repository.
+fn git_tracked_files(root: &Path) -> Option<Vec<String>> {
+    let output = process::Command::new("git")
+        .args(["ls-files", "-z", "--cached"])
+        .current_dir(root)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|l| !l.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Run git and return stdout, or None if it fails.
+fn git_output(root: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    let output = process::Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .output()
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+/// Resolve a path inside the git directory (`git rev-parse --git-path`). Handles
+/// worktrees and submodules, where `.git` is a file pointing elsewhere.
+fn git_path(root: &Path, name: &str) -> Option<PathBuf> {
+    let out = git_output(root, &["rev-parse", "--git-path", name])?;
+    let path = PathBuf::from(String::from_utf8_lossy(&out).trim());
+    Some(if path.is_absolute() {
+        path
+    } else {
+        root.join(path)
+    })
+}
+
+/// A commit from `log_changes`: its sha, parents and what it changes.
+struct LoggedCommit {
+    sha: String,
+    parents: Vec<String>,
+    /// Paths that differ from every parent (everything, for a root commit)
+    changes: Vec<Change>,
+    /// Whether the commit changes `.donttouch.toml` relative to any parent
+    edits_config: bool,
+}
+
+/// The commits `git log <args>` selects, oldest first, read with a single git call.
+fn log_changes(root: &Path, args: &[&str]) -> Option<Vec<LoggedCommit>> {
+    let mut log = vec![
+        "log",
+        "--reverse",
+        "--topo-order",
+        "--root",
+        "-m",
+        "--name-status",
+        "-z",
+        "-M",
+        "-C",
+        "--format=%x01%H %P",
+    ];
+    log.extend_from_slice(args);
+    let out = git_output(root, &log)?;
+    let out = String::from_utf8_lossy(&out);
+
+    // `-m` prints a merge once per parent it differs from
+    let mut commits: Vec<(LoggedCommit, Vec<Vec<Change>>)> = Vec::new();
+    for entry in out.split('\x01').filter(|e| !e.is_empty()) {
+        let (header, diff) = entry.split_once('\0').unwrap_or((entry, ""));
+        let mut ids = header.split_whitespace().map(String::from);
+        let Some(sha) = ids.next() else {
+            continue;
+        };
+        let changes = parse_name_status(diff.trim_start_matches('\n').as_bytes());
+        match commits.last_mut() {
+            Some((c, diffs)) if c.sha == sha => diffs.push(changes),
+            _ => commits.push((
+                LoggedCommit {
+                    sha,
+                    parents: ids.collect(),
+                    changes: Vec::new(),
+                    edits_config: false,
+                },
+                vec![changes],
+            )),
+        }
+    }
+
+    Some(
+        commits
+            .into_iter()
+            .map(|(mut commit, diffs)| {
+                commit.edits_config = diffs
+                    .iter()
+                    .flatten()
+                    .any(|c| c.paths().contains(&".donttouch.toml"));
+                // A parent missing from the log has no differences
+                commit.changes = if diffs.len() < commit.parents.len() {
+                    Vec::new()
+                } else {
+                    changed_from_every_parent(diffs)
+                };
+                commit
+            })
+            .collect(),
+    )
+}
+
+/// What a commit changes relative to its parents (or everything, for a root commit).
+/// A merge only counts paths that differ from every parent.
+fn commit_changes(root: &Path, sha: &str) -> Vec<Change> {
+    log_changes(root, &["--no-walk", sha])
+        .and_then(|commits| commits.into_iter().next())
+        .map(|c| c.changes)
+        .unwrap_or_default()
+}
+
+/// Commits selected by `git rev-list <args>` that touch protected paths, oldest first.
+///
+/// Every commit is judged by `patterns` plus the patterns committed in the parent
+/// of it and of every earlier commit in the range, so a commit can't lift
+/// protection for the commits after it.
+fn protected_commits(
+    root: &Path,
+    rev_list_args: &[&str],
+    patterns: &[Pattern],
+) -> Vec<(Option<String>, Vec<Change>)> {
+    let Some(commits) = log_changes(root, rev_list_args) else {
+        return Vec::new();
+    };
+
+    let config_tracked =
+        git_output(root, &["ls-files", "--error-unmatch", ".donttouch.toml"]).is_some();
+
+    let mut patterns = patterns.to_vec();
+    let add = |rev: &str, patterns: &mut Vec<Pattern>| {
+        for p in committed_patterns(root, rev) {
+            if !patterns.contains(&p) {
+                patterns.push(p);
+            }
+        }
+    };
+    // Configs are only read where they can differ: at parents outside the
+    // range, and after commits that edit the config
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut flagged = Vec::new();
+    for commit in commits {
+        if config_tracked {
+            for parent in &commit.parents {
+                if seen.insert(parent.clone()) {
+                    add(parent, &mut patterns);
+                }
+            }
+        }
+        seen.insert(commit.sha.clone());
+        let changes: Vec<Change> = commit
+            .changes
+            .into_iter()
+            .filter(|c| c.touches(&patterns))
+            .collect();
+        // A config edit applies to the commits after this one
+        if config_tracked && commit.edits_config {
+            add(&commit.sha, &mut patterns);
+        }
+        if !changes.is_empty() {
+            flagged.push((Some(commit.sha), changes));
+        }
+    }
+    flagged
+}
+
+/// `.donttouch.toml` as committed at `rev`, if it has a valid one.
+fn committed_config(root: &Path, rev: &str) -> Option<ConfigFile> {
+    let raw = git_output(root, &["show", &format!("{rev}:./.donttouch.toml")])?;
+    toml::from_str(&String::from_utf8_lossy(&raw)).ok()
+}
+
+/// Patterns from `.donttouch.toml` as committed at `rev` (empty if it has none).
+fn committed_patterns(root: &Path, rev: &str) -> Vec<Pattern> {
+    committed_config(root, rev)
+        .map(|config| compile_patterns(&config.protect.patterns()))
+        .unwrap_or_default()
+}
+
+/// Short hash and subject line, for naming commits in reports.
+fn describe_commit(root: &Path, sha: &str) -> String {
+    git_output(root, &["log", "-1", "--format=%h %s", sha])
+        .map(|o| String::from_utf8_lossy(&o).trim().to_string())
+        .unwrap_or_else(|| sha.to_string())
+}
+
+/// Re-read config patterns from disk for git staged file checking.
+/// (We need the raw patterns for matching against relative paths from git.)
+fn files_to_patterns(root: &Path) -> Vec<Pattern> {
+    let config_path = root.join(".donttouch.toml");
+    let content = match std::fs::read_to_string(&config_path) {
+        Ok(c) => c,
+        Err(_) => return Vec::new(),
+    };
+    let config: ConfigFile = match toml::from_str(&content) {
+        Ok(c) => c,
+        Err(_) => return Vec::new(),
+    };
+    compile_patterns(&config.protected_patterns(root))
+}
+
+// =============================================================================
+// Approvals
+// =============================================================================
+
+/// Protected changes not covered by an approval.
+struct Unapproved {
+    /// Commit, or None for the commit being created
+    sha: Option<String>,
+    changes: Vec<Change>,
+    /// Why approval failed (None when approvals aren't configured)
+    reason: Option<String>,
+}
+
+/// Value of trailer `key` in `git interpret-trailers --parse` style output.
+fn trailer_value(trailers: &str, key: &str) -> Option<String> {
+    trailers.lines().find_map(|line| {
+        let (k, v) = line.split_once(':')?;
+        let v = v.trim();
+        (k.trim().eq_ignore_ascii_case(key) && !v.is_empty()).then(|| v.to_string())
+    })
+}
+
+/// Approver named in the message file of the commit being created.
+fn message_approval(
+    root: &Path,
+    msg_file: Option<&str>,
+    approval: &ApprovalSection,
+) -> Result<String, Option<String>> {
+    let Some(key) = &approval.trailer else {
+        return Err(None);
+    };
+    let Some(msg_file) = msg_file else {
+        return Err(Some("no commit message file was passed to the hook".into()));
+    };
+    let trailers = git_output(root, &["interpret-trailers", "--parse", msg_file])
+        .map(|o| String::from_utf8_lossy(&o).into_owned())
+        .unwrap_or_default();
+    trailer_value(&trailers, key).ok_or_else(|| Some(format!("no '{key}:' trailer")))
+}
+
+/// Approver of an existing commit: the trailer, plus a good signature from an
+/// allowed key when `signers` is set.
+fn commit_approval(
+    root: &Path,
+    sha: &str,
+    approval: &ApprovalSection,
+) -> Result<String, Option<String>> {
+    let Some(key) = &approval.trailer else {
+        return Err(None);
+    };
+    let show = |format: &str| {
+        git_output(root, &["log", "-1", &format!("--format={format}"), sha])
+            .map(|o| String::from_utf8_lossy(&o).into_owned())
+            .unwrap_or_default()
+    };
+
+    let approver = trailer_value(&show("%(trailers:only,unfold)"), key)
+        .ok_or_else(|| Some(format!("no '{key}:' trailer")))?;
+    if approval.signers.is_empty() {
+        return Ok(approver);
+    }
+
+    // %G? is G (good) or U (good, unknown validity) for a verified signature;
+    // the allowlist is what establishes trust here
+    let signature = show("%G?%n%GF%n%GP");
+    let mut lines = signature.lines().map(str::trim);
+    let status = lines.next().unwrap_or("N");
+    let fingerprints: Vec<&str> = lines.filter(|l| !l.is_empty()).collect();
+    if !matches!(status, "G" | "U") {
+        return Err(Some(format!(
+            "'{key}:' trailer present but the commit has no valid signature (%G? = {status})"
+        )));
+    }
+    if approval
+        .signers
+        .iter()
+        .any(|allowed| fingerprints.iter().any(|f| signer_matches(allowed, f)))
+    {
+        Ok(format!("{approver}, signed by {}", fingerprints[0]))
+    } else {
+        Err(Some(format!(
+            "signed by {}, which is not in [approval] signers",
+            fingerprints.first().unwrap_or(&"an unknown key")
+        )))
+    }
+}
+
+/// Whether an allowlist entry names `fingerprint`. SSH fingerprints must match
+/// exactly; GPG ones ignore case and spaces, and a long key ID (16+ hex digits)
+/// matches the end of a full fingerprint.
+fn signer_matches(allowed: &str, fingerprint: &str) -> bool {
+    if allowed.starts_with("SHA256:") || fingerprint.starts_with("SHA256:") {
+        return allowed == fingerprint;
+    }
+    let normalize = |s: &str| s.replace(' ', "").to_uppercase();
+    let (allowed, fingerprint) = (normalize(allowed), normalize(fingerprint));
+    allowed.len() >= 16
+        && allowed.chars().all(|c| c.is_ascii_hexdigit())
+        && fingerprint.ends_with(&allowed)
+}
+
+/// Split protected commits into approved ones ("<commit> — approved by …") and the rest.
+fn review_commits(
+    root: &Path,
+    commits: Vec<(Option<String>, Vec<Change>)>,
+    approval: &ApprovalSection,
+) -> (Vec<String>, Vec<Unapproved>) {
+    let mut approved = Vec::new();
+    let mut unapproved = Vec::new();
+    for (sha, changes) in commits {
+        let verdict = match &sha {
+            Some(sha) => commit_approval(root, sha, approval),
+            None => Err(None),
+        };
+        match verdict {
+            Ok(approver) => approved.push(format!(
+                "✅ {} — approved by {approver}",
+                describe_commit(root, sha.as_deref().unwrap_or_default())
+            )),
+            Err(reason) => unapproved.push(Unapproved {
+                sha,
+                changes,
+                reason,
+            }),
+        }
+    }
+    (approved, unapproved)
+}
+
+fn render_unapproved(
+    out: &mut String,
+    root: &Path,
+    protect: &ProtectSection,
+    unapproved: &[Unapproved],
+) {
+    for u in unapproved {
+        let indent = match &u.sha {
+            Some(sha) => {
+                out.push_str(&format!("   {}\n", describe_commit(root, sha)));
+                "      "
+            }
+            None => "   ",
+        };
+        for c in &u.changes {
+            out.push_str(&protect.bullet(indent, c, &c.paths()));
+            out.push('\n');
+        }
+        if let Some(reason) = &u.reason {
+            out.push_str(&format!("{indent}⛔ not approved: {reason}\n"));
+        }
+    }
+}
+
+// =============================================================================
+// Plans
+// =============================================================================
+
+/// A file an operation writes, or deletes (`after` = None).
+struct FileChange {
+    path: PathBuf,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+/// A permission change, as mode bits. `before` is None for a file the plan creates.
+struct ModeChange {
+    path: PathBuf,
+    before: Option<u32>,
+    after: u32,
+}
+
+/// Everything an operation changes: previewed by `--dry-run`, applied otherwise.
+#[derive(Default)]
+struct Plan {
+    files: Vec<FileChange>,
+    modes: Vec<ModeChange>,
+    /// Hints and warnings shown with the changes
+    notes: Vec<String>,
+}
+
+/// Result of applying a plan: a line per change made, or the failure that
+/// rolled it back.
+#[derive(Default)]
+struct Applied {
+    lines: Vec<String>,
+    /// Permission changes made
+    modes: usize,
+    /// The step that failed, after which everything done was undone
+    error: Option<String>,
+    /// Steps the rollback couldn't undo
+    rollback_errors: Vec<String>,
+}
+
+/// How to undo one applied step.
+enum Undo {
+    Mode {
+        path: PathBuf,
+        mode: u32,
+    },
+    /// Put back the previous bytes and mode, or delete a file the plan created
+    File {
+        path: PathBuf,
+        before: Option<(Vec<u8>, u32)>,
+    },
+}
+
+impl Plan {
+    /// Write `after` to `path`, unless it already has that content.
+    fn write(&mut self, path: PathBuf, after: String) {
+        let before = std::fs::read_to_string(&path).ok();
+        if before.as_deref() != Some(after.as_str()) {
+            self.files.push(FileChange {
+                path,
+                before,
+                after: Some(after),
+            });
+        }
+    }
+
+    fn delete(&mut self, path: PathBuf) {
+        if std::fs::symlink_metadata(&path).is_ok() {
+            let before = std::fs::read_to_string(&path).ok();
+            self.files.push(FileChange {
+                path,
+                before,
+                after: None,
+            });
+        }
+    }
+
+    /// Make `path` read-only or writable, unless it already is.
+    fn set_readonly(&mut self, path: &Path, readonly: bool) {
+        let Some(before) = file_mode(path) else {
+            return;
+        };
+        let after = readonly_mode(before, readonly);
+        if after != before {
+            self.modes.push(ModeChange {
+                path: path.to_path_buf(),
+                before: Some(before),
+                after,
+            });
+        }
+    }
+
+    /// Give `path` exactly `mode`, once written if the plan creates it.
+    fn set_mode(&mut self, path: &Path, mode: u32) {
+        let before = file_mode(path);
+        if before != Some(mode) {
+            self.modes.push(ModeChange {
+                path: path.to_path_buf(),
+                before,
+                after: mode,
+            });
+        }
+    }
+
+    /// Like `set_readonly`, refusing links the symlink rules forbid following.
+    fn set_protected(&mut self, file: &ProtectedFile, readonly: bool) -> Result<(), String> {
+        match file.link.as_ref().and_then(|l| l.blocked) {
+            Some(reason) => Err(format!("{}: {reason}", file.path.display())),
+            None => {
+                self.set_readonly(&file.path, readonly);
+                Ok(())
+            }
+        }
+    }
+
+    fn extend(&mut self, other: Plan) {
+        self.files.extend(other.files);
+        self.modes.extend(other.modes);
+        self.notes.extend(other.notes);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.modes.is_empty()
+    }
+
+    /// Mode changes, then a unified diff per file, with paths relative to `root`.
+    fn preview(&self, root: &Path) -> String {
+        if self.is_empty() && self.notes.is_empty() {
+            return "Dry run — nothing to change.".into();
+        }
+        let mut out = String::from("Dry run — no changes made:\n");
+        for m in &self.modes {
+            let icon = if m.after & 0o200 == 0 { "🔒" } else { "🔓" };
+            let before = m.before.map_or("new".into(), |b| format!("{b:04o}"));
+            out.push_str(&format!(
+                "\n   {icon} {}  {before} → {:04o}",
+                m.path.strip_prefix(root).unwrap_or(&m.path).display(),
+                m.after
+            ));
+        }
+        if !self.modes.is_empty() {
+            out.push('\n');
+        }
+        if !self.files.is_empty() {
+            out.push('\n');
+            out.push_str(&self.diffs(root));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("\n{note}"));
+        }
+        out
+    }
+
+    /// A unified diff per written or deleted file.
+    fn diffs(&self, root: &Path) -> String {
+        let mut out = String::new();
+        for f in &self.files {
+            let rel = f
+                .path
+                .strip_prefix(root)
+                .unwrap_or(&f.path)
+                .display()
+                .to_string();
+            let from = match f.before {
+                Some(_) => format!("a/{rel}"),
+                None => "/dev/null".into(),
+            };
+            let to = match f.after {
+                Some(_) => format!("b/{rel}"),
+                None => "/dev/null".into(),
+            };
+            out.push_str(&unified_diff(
+                f.before.as_deref().unwrap_or_default(),
+                f.after.as_deref().unwrap_or_default(),
+                &from,
+                &to,
+            ));
+        }
+        out
+    }
+
+    /// Unlock, then write and delete, then lock — so files this plan leaves
+    /// read-only can still be edited by it, and files it creates get their
+    /// mode once they exist. The first failure rolls back every step already
+    /// taken, so the project is left as it was.
+    fn apply(&self, root: &Path) -> Applied {
+        let mut applied = Applied::default();
+        let mut undo = Vec::new();
+        if let Err(e) = self.apply_steps(root, &mut applied, &mut undo) {
+            applied.lines.clear();
+            applied.modes = 0;
+            applied.error = Some(e);
+            for step in undo.into_iter().rev() {
+                if let Err(e) = step.revert() {
+                    applied.rollback_errors.push(e);
+                }
+            }
+        }
+        applied
+    }
+
+    fn apply_steps(
+        &self,
+        root: &Path,
+        applied: &mut Applied,
+        undo: &mut Vec<Undo>,
+    ) -> Result<(), String> {
+        let (unlocks, locks): (Vec<&ModeChange>, Vec<&ModeChange>) = self
+            .modes
+            .iter()
+            .partition(|m| m.after & 0o200 != 0 && m.before.is_some());
+
+        let chmod = |m: &ModeChange, applied: &mut Applied, undo: &mut Vec<Undo>| {
+            let before = file_mode(&m.path);
+            set_file_mode(&m.path, m.after)?;
+            // A created file was already reported as created, and its undo deletes it
+            if let (Some(mode), Some(_)) = (before, m.before) {
+                undo.push(Undo::Mode {
+                    path: m.path.clone(),
+                    mode,
+                });
+                let icon = if m.after & 0o200 == 0 { "🔒" } else { "🔓" };
+                let rel = m.path.strip_prefix(root).unwrap_or(&m.path).display();
+                applied.lines.push(format!("   {icon} {rel}"));
+                applied.modes += 1;
+            }
+            Ok::<(), String>(())
+        };
+
+        for m in unlocks {
+            chmod(m, applied, undo)?;
+        }
+        for f in &self.files {
+            let rel = f.path.strip_prefix(root).unwrap_or(&f.path).display();
+            let before = std::fs::read(&f.path).ok().zip(file_mode(&f.path));
+            match &f.after {
+                Some(content) => write_atomic(&f.path, content.as_bytes())?,
+                None => std::fs::remove_file(&f.path)
+                    .map_err(|e| format!("Failed to remove {}: {e}", f.path.display()))?,
+            }
+            undo.push(Undo::File {
+                path: f.path.clone(),
+                before,
+            });
+            applied.lines.push(match (&f.before, &f.after) {
+                (_, None) => format!("   🗑️  Removed {rel}"),
+                (None, Some(_)) => format!("   📝 Created {rel}"),
+                (Some(_), Some(_)) => format!("   📝 Updated {rel}"),
+            });
+        }
+        for m in locks {
+            chmod(m, applied, undo)?;
+        }
+        Ok(())
+    }
+}
+
+impl Applied {
+    fn ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The change lines and notes, or the failure and how far rollback got.
+    fn render(&self, notes: &[String]) -> String {
+        let mut out = String::new();
+        if let Some(e) = &self.error {
+            out.push_str(&format!("   ❌ {e}\n"));
+            if self.rollback_errors.is_empty() {
+                out.push_str("↩️  Rolled back — nothing was changed.\n");
+            } else {
+                out.push_str("⚠️  Rollback incomplete; these may be left half-changed:\n");
+                for e in &self.rollback_errors {
+                    out.push_str(&format!("   ❌ {e}\n"));
+                }
+            }
+            return out;
+        }
+        for line in &self.lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        for note in notes {
+            out.push_str(note);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Undo {
+    fn revert(self) -> Result<(), String> {
+        match self {
+            Undo::Mode { path, mode } => set_file_mode(&path, mode),
+            Undo::File {
+                path,
+                before: Some((content, mode)),
+            } => {
+                write_atomic(&path, &content)?;
+                set_file_mode(&path, mode)
+            }
+            Undo::File { path, before: None } => std::fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove {}: {e}", path.display())),
+        }
+    }
+}
+
+/// Replace a file's content through a temp file and rename, so a crash leaves
+/// either the old or the new content. Keeps the file's mode (read-only
+/// included) and writes through symlinks.
+fn write_atomic(path: &Path, content: &[u8]) -> Result<(), String> {
+    let path = match std::fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_symlink() => std::fs::canonicalize(path)
+            .map_err(|e| format!("Cannot resolve {}: {e}", path.display()))?,
+        _ => path.to_path_buf(),
+    };
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(".{name}.donttouch-tmp"));
+
+    // A leftover from an interrupted write may be read-only
+    let _ = std::fs::remove_file(&tmp);
+    let written = std::fs::File::create(&tmp)
+        .and_then(|mut f| {
+            f.write_all(content)?;
+            f.sync_all()
+        })
+        .map_err(|e| format!("Failed to write {}: {e}", path.display()))
+        .and_then(|()| match file_mode(&path) {
+            Some(mode) => set_file_mode(&tmp, mode),
+            None => Ok(()),
+        })
+        .and_then(|()| {
+            std::fs::rename(&tmp, &path)
+                .map_err(|e| format!("Failed to replace {}: {e}", path.display()))
+        });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Lines of context around each change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// A line diff of `old` → `new` in unified format.
+fn unified_diff(old: &str, new: &str, from: &str, to: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&a, &b);
+
+    let mut out = format!("--- {from}\n+++ {to}\n");
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i].0 != ' ').collect();
+    let mut i = 0;
+    while i < changes.len() {
+        // Group changes whose context would overlap into one hunk
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= 2 * DIFF_CONTEXT {
+            j += 1;
+        }
+        let start = changes[i].saturating_sub(DIFF_CONTEXT);
+        let end = (changes[j] + DIFF_CONTEXT + 1).min(ops.len());
+
+        let old_before = ops[..start].iter().filter(|o| o.0 != '+').count();
+        let new_before = ops[..start].iter().filter(|o| o.0 != '-').count();
+        let old_len = ops[start..end].iter().filter(|o| o.0 != '+').count();
+        let new_len = ops[start..end].iter().filter(|o| o.0 != '-').count();
+        // An empty side is numbered by the line before it
+        let old_start = if old_len == 0 {
+            old_before
+        } else {
+            old_before + 1
+        };
+        let new_start = if new_len == 0 {
+            new_before
+        } else {
+            new_before + 1
+        };
+        out.push_str(&format!(
+            "@@ -{old_start},{old_len} +{new_start},{new_len} @@\n"
+        ));
+        for (op, line) in &ops[start..end] {
+            out.push_str(&format!("{op}{line}\n"));
+        }
+        i = j + 1;
+    }
+    out
+}
+
+/// Edit script from `a` to `b` as (' ' | '-' | '+', line), via the longest
+/// common subsequence. Very large inputs fall back to replacing everything
+/// between the common prefix and suffix.
+fn diff_lines<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<(char, &'a str)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops: Vec<(char, &str)> = a[..prefix].iter().map(|l| (' ', *l)).collect();
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n * m > 4_000_000 {
+        ops.extend(a_mid.iter().map(|l| ('-', *l)));
+        ops.extend(b_mid.iter().map(|l| ('+', *l)));
+    } else {
+        // lcs[i][j] = LCS length of a_mid[i..] and b_mid[j..]
+        let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if a_mid[i] == b_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && a_mid[i] == b_mid[j] {
+                ops.push((' ', a_mid[i]));
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                ops.push(('-', a_mid[i]));
+                i += 1;
+            } else {
+                ops.push(('+', b_mid[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(a[a.len() - suffix..].iter().map(|l| (' ', *l)));
+    ops
+}
+
+// =============================================================================
+// Backups
+// =============================================================================
+
+/// Where `remove` saves what it changes, relative to the project root.
+const BACKUP_DIR: &str = ".donttouch-backups";
+const BACKUP_VERSION: u32 = 1;
+
+/// Everything `remove` changed, as it was before, so `restore` can put it back.
+#[derive(Serialize, Deserialize)]
+struct Backup {
+    version: u32,
+    /// Seconds since the Unix epoch
+    created: u64,
+    /// The project the backup was taken from
+    root: String,
+    files: Vec<BackedUpFile>,
+}
+
+/// A file's mode, and its content if `remove` rewrote or deleted it.
+#[derive(Serialize, Deserialize)]
+struct BackedUpFile {
+    /// Relative to the root, or absolute for files outside it (e.g. a shared hooks dir)
+    path: String,
+    /// Octal, e.g. "0444"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+}
+
+impl Backup {
+    /// The current state of every file `plan` touches.
+    fn of(plan: &Plan, root: &Path) -> Backup {
+        let mut files: Vec<BackedUpFile> = Vec::new();
+        let touched = plan
+            .files
+            .iter()
+            .map(|f| (&f.path, f.before.as_ref()))
+            .chain(plan.modes.iter().map(|m| (&m.path, None)));
+        for (path, content) in touched {
+            let rel = path
+                .strip_prefix(root)
+                .unwrap_or(path)
+                .display()
+                .to_string();
+            match files.iter_mut().find(|f| f.path == rel) {
+                Some(f) => {
+                    if f.content.is_none() {
+                        f.content = content.cloned();
+                    }
+                }
+                None => files.push(BackedUpFile {
+                    path: rel,
+                    mode: file_mode(path).map(|m| format!("{m:04o}")),
+                    content: content.cloned(),
+                }),
+            }
+        }
+        Backup {
+            version: BACKUP_VERSION,
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            root: root.display().to_string(),
+            files,
+        }
+    }
+
+    /// Write the backup to a new read-only file under `BACKUP_DIR`.
+    fn save(&self, root: &Path) -> Result<PathBuf, String> {
+        let dir = root.join(BACKUP_DIR);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+        // Backups can hold hook scripts and agent files; keep them out of commits
+        let ignore = dir.join(".gitignore");
+        if !ignore.exists() {
+            std::fs::write(&ignore, "*\n")
+                .map_err(|e| format!("Failed to write {}: {e}", ignore.display()))?;
+        }
+
+        let stamp = utc_stamp(self.created);
+        let mut path = dir.join(format!("{stamp}.json"));
+        let mut n = 1;
+        while path.exists() {
+            n += 1;
+            path = dir.join(format!("{stamp}-{n}.json"));
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&path, json + "\n")
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+        set_file_readonly(&path, true)?;
+        Ok(path)
+    }
+
+    /// Writes and mode changes that put every backed-up file back.
+    fn plan(&self, root: &Path) -> Result<Plan, String> {
+        let mut plan = Plan::default();
+        let mut outside_dirs: Option<Vec<PathBuf>> = None;
+        for f in &self.files {
+            let rel = Path::new(&f.path);
+            // Absolute paths are only recorded for hooks outside the working tree
+            let escapes = rel
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+                || (rel.is_absolute()
+                    && !outside_dirs
+                        .get_or_insert_with(|| restorable_outside_dirs(root))
+                        .iter()
+                        .any(|d| rel.starts_with(d)));
+            if escapes {
+                return Err(format!(
+                    "Refusing to restore '{}': path leaves the project",
+                    f.path
+                ));
+            }
+            let path = root.join(rel);
+            if let Some(content) = &f.content {
+                plan.write(path.clone(), content.clone());
+            }
+            if let Some(mode) = &f.mode {
+                let mode = u32::from_str_radix(mode, 8)
+                    .map_err(|_| format!("Invalid mode '{mode}' for {}", f.path))?;
+                plan.set_mode(&path, mode);
+            }
+        }
+        if Path::new(&self.root) != root {
+            plan.notes
+                .push(format!("⚠️  This backup was taken from {}.", self.root));
+        }
+        Ok(plan)
+    }
+}
+
+/// Directories outside the working tree a backup may write to: the git dir and
+/// the hooks dir (which `core.hooksPath` can point anywhere).
+fn restorable_outside_dirs(root: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(out) = git_output(
+        root,
+        &[
+            "rev-parse",
+            "--path-format=absolute",
+            "--git-dir",
+            "--git-common-dir",
+        ],
+    ) {
+        dirs.extend(String::from_utf8_lossy(&out).lines().map(PathBuf::from));
+    }
+    if root.join(".git").exists() {
+        dirs.push(resolve_hooks_dir(root, false));
+    }
+    let canonical: Vec<PathBuf> = dirs
+        .iter()
+        .filter_map(|d| std::fs::canonicalize(d).ok())
+        .collect();
+    dirs.extend(canonical);
+    dirs
+}
+
+/// `secs` since the Unix epoch as a UTC `YYYYMMDD-HHMMSS` stamp.
+fn utc_stamp(secs: u64) -> String {
+    let (days, time) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from a day count (Howard Hinnant's days_from_civil, inverted)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+// =============================================================================
+// Filesystem Helpers
+// =============================================================================
+
+fn compile_patterns(raw: &[String]) -> Vec<Pattern> {
+    raw.iter()
+        .filter_map(|p| match Pattern::new(p) {
+            Ok(pat) => Some(pat),
+            Err(e) => {
+                eprintln!("donttouch: bad glob pattern '{p}': {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Compiled `[discover] skip` entries. `.git` is always skipped.
+struct SkipRules {
+    /// Entries without a `/`, matched against a directory's name
+    names: Vec<Pattern>,
+    /// Entries with a `/`, matched against a directory's root-relative path
+    paths: Vec<Pattern>,
+}
+
+impl SkipRules {
+    fn new(raw: &[String]) -> Self {
+        let mut rules = SkipRules {
+            names: Vec::new(),
+            paths: Vec::new(),
+        };
+        for entry in raw {
+            let entry = entry.trim_matches('/');
+            match Pattern::new(entry) {
+                Ok(p) if entry.contains('/') => rules.paths.push(p),
+                Ok(p) => rules.names.push(p),
+                Err(e) => eprintln!("donttouch: bad skip pattern '{entry}': {e}"),
+            }
+        }
+        rules
+    }
+
+    fn matches(&self, rel: &str, name: &str) -> bool {
+        name == ".git"
+            || self.names.iter().any(|p| p.matches(name))
+            || self.paths.iter().any(|p| p.matches(rel))
+    }
+
+    /// Whether any directory containing `rel` (a file path) is skipped.
+    fn skips_parent_of(&self, rel: &str) -> bool {
+        let mut end = 0;
+        let parts: Vec<&str> = rel.split('/').collect();
+        for part in &parts[..parts.len().saturating_sub(1)] {
+            end += part.len();
+            if self.matches(&rel[..end], part) {
+                return true;
+            }
+            end += 1;
+        }
+        false
+    }
+}
+
+fn discover_files(root: &Path, patterns: &[Pattern], opts: &DiscoverSection) -> Vec<ProtectedFile> {
+    let canonical_root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let mut results: Vec<ProtectedFile> = discover_paths(root, patterns, opts, "donttouch-cache")
+        .into_iter()
+        .map(|rel| {
+            let path = root.join(rel);
+            ProtectedFile {
+                readonly: is_file_readonly(&path),
+                link: inspect_symlink(&path, &canonical_root, opts),
+                path,
+            }
+        })
+        .collect();
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+    results
+}
+
+/// Root-relative paths matching `patterns`, found the way `[discover]` says:
+/// git's index or a parallel walk, cached in the git dir's `cache_name`.
+fn discover_paths(
+    root: &Path,
+    patterns: &[Pattern],
+    opts: &DiscoverSection,
+    cache_name: &str,
+) -> Vec<String> {
+    let skip = SkipRules::new(&opts.skip);
+    let canonical_root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+
+    let tracked = if opts.tracked_only {
+        git_tracked_files(root)
+    } else {
+        None
+    };
+
+    match tracked {
+        Some(tracked) => {
+            let mut files: Vec<String> = tracked
+                .into_iter()
+                .filter(|rel| !skip.skips_parent_of(rel) && patterns.iter().any(|p| p.matches(rel)))
+                .collect();
+            // Untracked files are still protected when a pattern names their path
+            let named: Vec<Pattern> = patterns
+                .iter()
+                .filter(|p| !literal_prefix(p.as_str()).is_empty())
+                .cloned()
+                .collect();
+            if !named.is_empty() {
+                let walk = WalkOptions {
+                    skip: &skip,
+                    canonical_root: canonical_root.clone(),
+                    followed: Mutex::new(HashSet::new()),
+                    prefixes: literal_prefixes(&named),
+                    explicit: Vec::new(),
+                    gitignore: false,
+                    stamps: false,
+                };
+                let (untracked, _) = walk_parallel(root, &named, &walk, opts.threads);
+                for rel in untracked {
+                    if !files.contains(&rel) {
+                        files.push(rel);
+                    }
+                }
+            }
+            files
+        }
+        None => {
+            let cache = if opts.cache {
+                cache_path(root, cache_name)
+            } else {
+                None
+            };
+            let key = cache_key(patterns, opts);
+            match cache.as_deref().and_then(|c| load_cache(c, root, key)) {
+                Some(files) => files,
+                None => {
+                    let walk = WalkOptions {
+                        skip: &skip,
+                        canonical_root,
+                        followed: Mutex::new(HashSet::new()),
+                        prefixes: literal_prefixes(patterns),
+                        explicit: explicit_prefixes(patterns),
+                        gitignore: opts.gitignore,
+                        stamps: cache.is_some(),
+                    };
+                    let (files, stamps) = walk_parallel(root, patterns, &walk, opts.threads);
+                    if let Some(c) = &cache {
+                        save_cache(c, key, &stamps, &files);
+                    }
+                    files
+                }
+            }
+        }
+    }
+}
+
+/// Classify a protected path that is a symlink. Returns None for regular files.
+fn inspect_symlink(path: &Path, canonical_root: &Path, opts: &DiscoverSection) -> Option<Symlink> {
+    if !std::fs::symlink_metadata(path)
+        .ok()?
+        .file_type()
+        .is_symlink()
+    {
+        return None;
+    }
+    let target = std::fs::canonicalize(path).ok();
+    let blocked = match &target {
+        None => Some("dangling symlink"),
+        Some(t) if !t.starts_with(canonical_root) => Some("symlink target is outside the project"),
+        Some(_) if !opts.protect_link_targets => {
+            Some("symlink targets are not protected (protect_link_targets = false)")
+        }
+        Some(_) => None,
+    };
+    Some(Symlink { target, blocked })
+}
+
+struct WalkOptions<'a> {
+    skip: &'a SkipRules,
+    /// Canonical project root; symlinked directories resolving outside it are not followed
+    canonical_root: PathBuf,
+    /// Canonical targets of symlinked directories already followed
+    followed: Mutex<HashSet<PathBuf>>,
+    /// Literal leading text of every pattern, used to prune directories
+    prefixes: Vec<String>,
+    /// Non-empty prefixes; ignored directories they reach into are still walked
+    explicit: Vec<String>,
+    gitignore: bool,
+    /// Record directory and ignore-file mtimes for the discovery cache
+    stamps: bool,
+}
+
+/// Literal text of each pattern up to its first glob metacharacter.
+fn literal_prefixes(patterns: &[Pattern]) -> Vec<String> {
+    patterns
+        .iter()
+        .map(|p| literal_prefix(p.as_str()))
+        .collect()
+}
+
+/// Prefixes of patterns that name a concrete path rather than starting with a wildcard.
+fn explicit_prefixes(patterns: &[Pattern]) -> Vec<String> {
+    literal_prefixes(patterns)
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn literal_prefix(pattern: &str) -> String {
+    let end = pattern.find(['*', '?', '[']).unwrap_or(pattern.len());
+    pattern[..end].to_string()
+}
+
+/// Whether any pattern could match a file below root-relative directory `rel_dir`.
+/// `*` may match `/`, so this compares character prefixes rather than path components.
+fn may_contain_matches(prefixes: &[String], rel_dir: &str) -> bool {
+    let dir = format!("{rel_dir}/");
+    prefixes
+        .iter()
+        .any(|p| p.starts_with(&dir) || dir.starts_with(p.as_str()))
+}
+
+/// A directory waiting to be read, with the ignore files that apply to it.
+struct WalkJob {
+    dir: PathBuf,
+    ignores: Vec<Arc<IgnoreFile>>,
+}
+
+/// Shared work queue: pending directories plus the number being processed.
+struct WalkQueue {
+    state: Mutex<(Vec<WalkJob>, usize)>,
+    ready: Condvar,
+}
+
+impl WalkQueue {
+    /// Take the next directory, waiting while other workers may still add more.
+    fn pop(&self) -> Option<WalkJob> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.0.pop() {
+                state.1 += 1;
+                return Some(job);
+            }
+            if state.1 == 0 {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    fn finish(&self, found: Vec<WalkJob>) {
+        let mut state = self.state.lock().unwrap();
+        state.0.extend(found);
+        state.1 -= 1;
+        self.ready.notify_all();
+    }
+}
+
+/// Walk `root` with `threads` workers (0 = one per CPU). Returns matching
+/// root-relative paths and, if requested, cache stamps.
+fn walk_parallel(
+    root: &Path,
+    patterns: &[Pattern],
+    opts: &WalkOptions,
+    threads: usize,
+) -> (Vec<String>, Vec<Stamp>) {
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get().min(8)),
+        n => n,
+    };
+
+    let mut ignores = Vec::new();
+    if opts.gitignore {
+        let exclude_path =
+            git_path(root, "info/exclude").unwrap_or_else(|| root.join(".git/info/exclude"));
+        if let Some(exclude) = parse_ignore_file(&exclude_path, "") {
+            ignores.push(Arc::new(exclude));
+        }
+    }
+    let queue = WalkQueue {
+        state: Mutex::new((
+            vec![WalkJob {
+                dir: root.to_path_buf(),
+                ignores,
+            }],
+            0,
+        )),
+        ready: Condvar::new(),
+    };
+
+    let worker = || {
+        let mut files = Vec::new();
+        let mut stamps = Vec::new();
+        while let Some(job) = queue.pop() {
+            let found = walk_one(root, job, patterns, opts, &mut files, &mut stamps);
+            queue.finish(found);
+        }
+        (files, stamps)
+    };
+
+    let (mut files, mut stamps) = (Vec::new(), Vec::new());
+    if threads == 1 {
+        (files, stamps) = worker();
+    } else {
+        std::thread::scope(|s| {
+            let handles: Vec<_> = (0..threads).map(|_| s.spawn(worker)).collect();
+            for h in handles {
+                let (f, st) = h.join().unwrap();
+                files.extend(f);
+                stamps.extend(st);
+            }
+        });
+    }
+    (files, stamps)
+}
+
+/// Read one directory: collect matching files and return subdirectories to visit.
+fn walk_one(
+    base: &Path,
+    job: WalkJob,
+    patterns: &[Pattern],
+    opts: &WalkOptions,
+    files: &mut Vec<String>,
+    stamps: &mut Vec<Stamp>,
+) -> Vec<WalkJob> {
+    let WalkJob { dir, mut ignores } = job;
+    let rel_dir = dir
+        .strip_prefix(base)
+        .unwrap_or(&dir)
+        .to_string_lossy()
+        .into_owned();
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+    if opts.stamps {
+        stamps.push(Stamp::of(&dir, rel_dir.clone()));
+    }
+
+    if opts.gitignore {
+        for name in [".gitignore", ".ignore"] {
+            let path = dir.join(name);
+            if let Some(file) = parse_ignore_file(&path, &rel_dir) {
+                if opts.stamps {
+                    stamps.push(Stamp::of(&path, join_rel(&rel_dir, name)));
+                }
+                ignores.push(Arc::new(file));
+            }
+        }
+    }
+
+    let mut subdirs = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let rel = join_rel(&rel_dir, &name);
+
+        // file_type() comes from the directory entry and avoids a stat call
+        let is_dir = match entry.file_type() {
+            Ok(t) if t.is_symlink() => {
+                if !entry.path().is_dir() {
+                    false
+                } else if follow_dir_link(&entry.path(), &dir, opts) {
+                    true
+                } else {
+                    continue;
+                }
+            }
+            Ok(t) => t.is_dir(),
+            Err(_) => continue,
+        };
+
+        if is_dir && opts.skip.matches(&rel, &name) {
+            continue;
+        }
+        // Ignore files only prune the walk: a file a pattern matches, or a
+        // directory a pattern names a path inside, is still protected
+        if opts.gitignore && is_ignored(&ignores, &rel, is_dir) {
+            let named = if is_dir {
+                may_contain_matches(&opts.explicit, &rel)
+            } else {
+                patterns.iter().any(|p| p.matches(&rel))
+            };
+            if !named {
+                continue;
+            }
+        }
+
+        if is_dir {
+            if may_contain_matches(&opts.prefixes, &rel) {
+                subdirs.push(WalkJob {
+                    dir: entry.path(),
+                    ignores: ignores.clone(),
+                });
+            }
+        } else if patterns.iter().any(|p| p.matches(&rel)) {
+            files.push(rel);
+        }
+    }
+    subdirs
+}
+
+/// Decide whether to descend into a symlinked directory. Links are followed only
+/// when they resolve inside the project, don't point at one of their own
+/// ancestors (a cycle), and haven't already been followed from another link.
+fn follow_dir_link(link: &Path, parent: &Path, opts: &WalkOptions) -> bool {
+    let Ok(target) = std::fs::canonicalize(link) else {
+        return false;
+    };
+    if !target.starts_with(&opts.canonical_root) {
+        return false;
+    }
+    let parent = std::fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+    if parent.starts_with(&target) {
+        return false;
+    }
+    opts.followed.lock().unwrap().insert(target)
+}
+
+fn join_rel(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+// =============================================================================
+// Discovery Cache
+// =============================================================================
+
+const CACHE_HEADER: &str = "donttouch-cache v2";
+
+/// Modification time of a directory (or ignore file) seen during a walk.
+/// If none of them changed, the set of matching files cannot have changed.
+struct Stamp {
+    path: String,
+    mtime: u128,
+}
+
+impl Stamp {
+    fn of(path: &Path, rel: String) -> Self {
+        Stamp {
+            path: rel,
+            mtime: mtime_nanos(path).unwrap_or(0),
+        }
+    }
+}
+
+fn mtime_nanos(path: &Path) -> Option<u128> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(
+        modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_nanos(),
+    )
+}
+
+/// Caches live inside the git directory so they never show up as project files.
+fn cache_path(root: &Path, name: &str) -> Option<PathBuf> {
+    if !root.join(".git").exists() {
+        return None;
+    }
+    git_path(root, name)
+}
+
+/// Hash of everything that affects which files match.
+fn cache_key(patterns: &[Pattern], opts: &DiscoverSection) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    for p in patterns {
+        p.as_str().hash(&mut h);
+    }
+    opts.skip.hash(&mut h);
+    opts.gitignore.hash(&mut h);
+    h.finish()
+}
+
+fn load_cache(cache: &Path, root: &Path, key: u64) -> Option<Vec<String>> {
+    let content = std::fs::read_to_string(cache).ok()?;
+    let mut lines = content.lines();
+    if lines.next()? != format!("{CACHE_HEADER} {key:x}") {
+        return None;
+    }
+
+    let mut files = Vec::new();
+    for line in lines {
+        if let Some(rest) = line.strip_prefix("d ") {
+            let (mtime, rel) = rest.split_once(' ').unwrap_or((rest, ""));
+            if mtime_nanos(&root.join(rel)) != mtime.parse().ok() {
+                return None;
+            }
+        } else if let Some(rel) = line.strip_prefix("f ") {
+            files.push(rel.to_string());
+        }
+    }
+    Some(files)
+}
+
+fn save_cache(cache: &Path, key: u64, stamps: &[Stamp], files: &[String]) {
+    // A directory modified within the filesystem's timestamp resolution of the
+    // walk could change again without its mtime moving, so don't trust it yet.
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    if stamps
+        .iter()
+        .any(|s| now.saturating_sub(s.mtime) < 2_000_000_000)
+    {
+        let _ = std::fs::remove_file(cache);
+        return;
+    }
+
+    let mut out = format!("{CACHE_HEADER} {key:x}\n");
+    for s in stamps {
+        out.push_str(&format!("d {} {}\n", s.mtime, s.path));
+    }
+    for f in files {
+        out.push_str(&format!("f {f}\n"));
+    }
+    // Atomic, since commands that don't take the process lock save it too
+    let _ = write_atomic(cache, out.as_bytes());
+}
+
+// =============================================================================
+// Ignore Files
+// =============================================================================
+
+/// Rules from one `.gitignore` / `.ignore` file.
+struct IgnoreFile {
+    /// Root-relative directory the file lives in ("" for the root)
+    base: String,
+    rules: Vec<IgnoreRule>,
+}
+
+struct IgnoreRule {
+    pattern: Pattern,
+    negate: bool,
+    dir_only: bool,
+    /// Patterns containing a `/` match the path relative to the ignore file;
+    /// others match the file name at any depth.
+    anchored: bool,
+}
+
+fn parse_ignore_file(path: &Path, base: &str) -> Option<IgnoreFile> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let rules: Vec<IgnoreRule> = content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.strip_prefix('/').unwrap_or(line);
+            Pattern::new(line).ok().map(|pattern| IgnoreRule {
+                pattern,
+                negate,
+                dir_only,
+                anchored,
+            })
+        })
+        .collect();
+
+    if rules.is_empty() {
+        None
+    } else {
+        Some(IgnoreFile {
+            base: base.to_string(),
+            rules,
+        })
+    }
+}
+
+/// Apply ignore files from outermost to innermost; the last matching rule wins.
+fn is_ignored(files: &[Arc<IgnoreFile>], rel: &str, is_dir: bool) -> bool {
+    let opts = glob::MatchOptions {
+        require_literal_separator: true,
+        ..glob::MatchOptions::new()
+    };
+    let mut ignored = false;
+
+    for file in files {
+        let sub = if file.base.is_empty() {
+            rel
+        } else {
+            match rel
+                .strip_prefix(file.base.as_str())
+                .and_then(|r| r.strip_prefix('/'))
+            {
+                Some(r) => r,
+                None => continue,
+            }
+        };
+        let name = sub.rsplit('/').next().unwrap_or(sub);
+
+        for rule in &file.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let target = if rule.anchored { sub } else { name };
+            if rule.pattern.matches_with(target, opts) {
+                ignored = !rule.negate;
+            }
+        }
+    }
+
+    ignored
+}
+
+#[cfg(unix)]
+fn is_file_readonly(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| (m.permissions().mode() & 0o200) == 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_file_readonly(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|m| m.permissions().readonly())
+        .unwrap_or(false)
+}
+
+fn set_file_readonly(path: &Path, readonly: bool) -> Result<(), String> {
+    let mode = file_mode(path).ok_or_else(|| format!("Cannot read {}", path.display()))?;
+    set_file_mode(path, readonly_mode(mode, readonly))
+}
+
+/// `mode` with write permission removed for everyone, or restored for the owner.
+fn readonly_mode(mode: u32, readonly: bool) -> u32 {
+    if readonly {
+        mode & !0o222
+    } else {
+        mode | 0o200
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .ok()
+        .map(|m| m.permissions().mode() & 0o7777)
+}
+
+/// Without unix modes, read-only is shown as 0444 and writable as 0644.
+#[cfg(not(unix))]
+fn file_mode(path: &Path) -> Option<u32> {
+    std::fs::metadata(path).ok().map(|m| {
+        if m.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        }
+    })
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &Path, mode: u32) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Cannot set permissions on {}: {e}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(path: &Path, mode: u32) -> Result<(), String> {
+    let meta =
+        std::fs::metadata(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+    let mut perms = meta.permissions();
+    perms.set_readonly(mode & 0o200 == 0);
+    std::fs::set_permissions(path, perms)
+        .map_err(|e| format!("Cannot set permissions on {}: {e}", path.display()))
+}
+
+/// The config file's content with `enabled` set.
+fn enabled_content(root: &Path, enabled: bool) -> Result<String, String> {
+    let config_path = root.join(".donttouch.toml");
+    let content = std::fs::read_to_string(&config_path)
+        .map_err(|e| format!("Could not read {}: {e}", config_path.display()))?;
+
+    let new_content = if content.contains("enabled") {
+        content
+            .lines()
+            .map(|line| {
+                if line.trim().starts_with("enabled") && line.contains('=') {
+                    format!("enabled = {enabled}")
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n"
+    } else {
+        let mut lines: Vec<String> = content.lines().map(String::from).collect();
+        if let Some(idx) = lines.iter().position(|l| l.trim() == "[protect]") {
+            lines.insert(idx + 1, format!("enabled = {enabled}"));
+        }
+        lines.join("\n") + "\n"
+    };
+
+    Ok(new_content)
+}
+
+// =============================================================================
+// Main
+// =============================================================================
+
+fn main() {
+    let cli = Cli::parse();
+    let start = State::Start {
+        command: cli.command,
+        ignoregit: cli.ignoregit,
+    };
+    start.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A scratch directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "donttouch-test-{}-{}",
+                process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(std::fs::canonicalize(path).unwrap())
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+
+        /// Write `content` to `rel`, creating parent directories.
+        fn write(&self, rel: &str, content: &str) -> PathBuf {
+            let path = self.0.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+
+        /// Run git in this directory with a throwaway identity, returning stdout.
+        fn git(&self, args: &[&str]) -> String {
+            let output = process::Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=t",
+                    "-c",
+                    "user.email=t@t",
+                    "-c",
+                    "init.defaultBranch=main",
+                    "-c",
+                    "commit.gpgsign=false",
+                    "-c",
+                    "protocol.file.allow=always",
+                ])
+                .args(args)
+                .current_dir(&self.0)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "git {args:?}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn config(toml: &str) -> ConfigFile {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn discovered(root: &Path, patterns: &[&str], opts: &DiscoverSection) -> Vec<String> {
+        let patterns: Vec<Pattern> = patterns.iter().map(|p| Pattern::new(p).unwrap()).collect();
+        discover_files(root, &patterns, opts)
+            .iter()
+            .map(|f| {
+                f.path
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    // -------------------------------------------------------------------------
+    // Discovery
+    // -------------------------------------------------------------------------
+
+    /// `.env` and `secrets/` ignored, plus a wildcard-only match in ignored `build/`
+    fn ignored_fixture() -> TempDir {
+        let dir = TempDir::new();
+        dir.write(".gitignore", ".env\nsecrets/\nbuild/\n");
+        dir.write(".env", "TOKEN=1");
+        dir.write("secrets/key.pem", "");
+        dir.write("build/out.pem", "");
+        dir
+    }
+
+    #[test]
+    fn gitignore_keeps_files_patterns_name() {
+        let dir = ignored_fixture();
+        let opts = DiscoverSection {
+            gitignore: true,
+            ..DiscoverSection::default()
+        };
+        let found = discovered(dir.path(), &[".env", "secrets/*.pem", "**/*.pem"], &opts);
+        assert_eq!(found, [".env", "secrets/key.pem"]);
+    }
+
+    #[test]
+    fn tracked_only_keeps_untracked_files_patterns_name() {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write("Cargo.lock", "");
+        dir.git(&["add", "Cargo.lock"]);
+        dir.git(&["commit", "-qm", "init"]);
+        dir.write(".env", "TOKEN=1");
+        dir.write("nested/untracked.lock", "");
+        let opts = DiscoverSection {
+            tracked_only: true,
+            ..DiscoverSection::default()
+        };
+        let found = discovered(dir.path(), &["Cargo.lock", ".env", "**/*.lock"], &opts);
+        assert_eq!(found, [".env", "Cargo.lock"]);
+    }
+
+    #[test]
+    fn doctor_warns_about_files_hidden_by_gitignore() {
+        let dir = ignored_fixture();
+        let toml = "[protect]\npatterns = [\".env\", \"**/*.pem\"]\n[discover]\ngitignore = true\n";
+        dir.write(".donttouch.toml", toml);
+        let state = do_doctor(&config(toml), dir.path(), &Context::Plain);
+        let message = state_message(&state);
+        assert!(message.contains("'build/out.pem'"), "{message}");
+        assert!(!message.contains("'.env' matches a pattern"), "{message}");
+    }
+
+    // -------------------------------------------------------------------------
+    // Pruning, parallel walk and cache
+    // -------------------------------------------------------------------------
+
+    fn prefixes(patterns: &[&str]) -> Vec<String> {
+        let patterns: Vec<Pattern> = patterns.iter().map(|p| Pattern::new(p).unwrap()).collect();
+        literal_prefixes(&patterns)
+    }
+
+    #[test]
+    fn prefix_pruning_follows_the_literal_text() {
+        // `**` and a leading wildcard reach every directory
+        assert!(may_contain_matches(&prefixes(&["**/*.pem"]), "deep/er"));
+        assert!(may_contain_matches(&prefixes(&["*.lock"]), "crates/a"));
+        // A character class ends the literal text, so `src/[ab]` could be anywhere under `src/`
+        let class = prefixes(&["src/[ab]*/mod.rs"]);
+        assert!(may_contain_matches(&class, "src"));
+        assert!(may_contain_matches(&class, "src/zeta"));
+        assert!(!may_contain_matches(&class, "lib"));
+        // `*` crosses `/`, so `config*` also reaches `config-old/nested`
+        let anchored = prefixes(&["config/app.yml", "config*.toml"]);
+        assert!(may_contain_matches(&anchored, "config"));
+        assert!(may_contain_matches(&anchored, "config-old/nested"));
+        assert!(!may_contain_matches(&anchored, "conf"));
+        assert!(!may_contain_matches(&anchored, "src"));
+        // A literal path only reaches its own ancestors
+        let literal = prefixes(&["a/b/c.txt"]);
+        assert!(may_contain_matches(&literal, "a"));
+        assert!(may_contain_matches(&literal, "a/b"));
+        assert!(!may_contain_matches(&literal, "a/bc"));
+        assert!(!may_contain_matches(&literal, "b"));
+    }
+
+    #[test]
+    fn pruning_never_hides_a_match() {
+        let dir = TempDir::new();
+        let files = [
+            "a/b/c.txt",
+            "src/alpha/mod.rs",
+            "src/beta/mod.rs",
+            "config-old/nested/x.toml",
+            "deep/er/key.pem",
+            "x.lock",
+        ];
+        for f in files {
+            dir.write(f, "");
+        }
+        let patterns = [
+            "a/b/c.txt",
+            "src/[ab]*/mod.rs",
+            "config*.toml",
+            "**/*.pem",
+            "*.lock",
+        ];
+        let found = discovered(dir.path(), &patterns, &DiscoverSection::default());
+        let mut expected: Vec<&str> = files.to_vec();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    /// Sorted results of a walk with `threads` workers.
+    fn walk_with(root: &Path, patterns: &[&str], threads: usize) -> Vec<String> {
+        let patterns: Vec<Pattern> = patterns.iter().map(|p| Pattern::new(p).unwrap()).collect();
+        let skip = SkipRules::new(&["skipped".to_string()]);
+        let opts = WalkOptions {
+            prefixes: literal_prefixes(&patterns),
+            ..walk_options(root, &skip)
+        };
+        let mut files = walk_parallel(root, &patterns, &opts, threads).0;
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn parallel_walk_matches_a_serial_walk() {
+        let dir = TempDir::new();
+        for a in 0..6 {
+            for b in 0..6 {
+                dir.write(&format!("d{a}/e{b}/f.lock"), "");
+                dir.write(&format!("d{a}/e{b}/g.txt"), "");
+            }
+            dir.write(&format!("d{a}/skipped/h.lock"), "");
+        }
+        let patterns = ["**/*.lock", "d1/*.txt"];
+        let serial = walk_with(dir.path(), &patterns, 1);
+        assert_eq!(serial.len(), 36 + 6);
+        for threads in [2, 8] {
+            assert_eq!(walk_with(dir.path(), &patterns, threads), serial);
+        }
+    }
+
+    /// Backdate every directory and ignore file, so the cache trusts their mtimes.
+    fn age(path: &Path) {
+        let past = std::time::SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::open(path)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path).unwrap().flatten() {
+                let name = entry.file_name();
+                if name != ".git" && (entry.path().is_dir() || name == ".gitignore") {
+                    age(&entry.path());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cache_is_reused_until_something_changes() {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write("a/x.lock", "");
+        dir.write("ignored/z.lock", "");
+        dir.write(".gitignore", "ignored/\n");
+        age(dir.path());
+        let opts = DiscoverSection {
+            cache: true,
+            gitignore: true,
+            ..DiscoverSection::default()
+        };
+        let cache = cache_path(dir.path(), "donttouch-cache").unwrap();
+        let lock = [Pattern::new("**/*.lock").unwrap()];
+        let key = cache_key(&lock, &opts);
+
+        assert_eq!(discovered(dir.path(), &["**/*.lock"], &opts), ["a/x.lock"]);
+        assert_eq!(load_cache(&cache, dir.path(), key).unwrap(), ["a/x.lock"]);
+
+        // A new file changes its directory's mtime
+        dir.write("a/y.lock", "");
+        assert!(load_cache(&cache, dir.path(), key).is_none());
+        let found = discovered(dir.path(), &["**/*.lock"], &opts);
+        assert_eq!(found, ["a/x.lock", "a/y.lock"]);
+
+        // Different patterns or options use a different key
+        age(dir.path());
+        discovered(dir.path(), &["**/*.lock"], &opts);
+        assert!(load_cache(&cache, dir.path(), key).is_some());
+        let txt = [Pattern::new("**/*.txt").unwrap()];
+        assert_ne!(cache_key(&txt, &opts), key);
+        let walk_all = DiscoverSection {
+            gitignore: false,
+            ..opts
+        };
+        assert_ne!(cache_key(&lock, &walk_all), key);
+
+        // Editing an ignore file invalidates it too
+        std::fs::write(dir.path().join(".gitignore"), "").unwrap();
+        assert!(load_cache(&cache, dir.path(), key).is_none());
+        let opts = DiscoverSection {
+            cache: true,
+            gitignore: true,
+            ..DiscoverSection::default()
+        };
+        let found = discovered(dir.path(), &["**/*.lock"], &opts);
+        assert_eq!(found, ["a/x.lock", "a/y.lock", "ignored/z.lock"]);
+    }
+
+    // -------------------------------------------------------------------------
+    // Symlinks
+    // -------------------------------------------------------------------------
+
+    fn walk_options<'a>(root: &Path, skip: &'a SkipRules) -> WalkOptions<'a> {
+        WalkOptions {
+            skip,
+            canonical_root: root.to_path_buf(),
+            followed: Mutex::new(HashSet::new()),
+            prefixes: vec![String::new()],
+            explicit: Vec::new(),
+            gitignore: false,
+            stamps: false,
+        }
+    }
+
+    fn link(dir: &TempDir, rel: &str, target: &Path) -> PathBuf {
+        let path = dir.path().join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::os::unix::fs::symlink(target, &path).unwrap();
+        path
+    }
+
+    #[test]
+    fn follow_dir_link_refuses_cycles() {
+        let dir = TempDir::new();
+        dir.write("a/b/x.lock", "");
+        let up = link(&dir, "a/b/up", &dir.path().join("a"));
+        let skip = SkipRules::new(&[]);
+        let opts = walk_options(dir.path(), &skip);
+        assert!(!follow_dir_link(&up, &dir.path().join("a/b"), &opts));
+
+        let found = discovered(dir.path(), &["**/*.lock"], &DiscoverSection::default());
+        assert_eq!(found, ["a/b/x.lock"]);
+    }
+
+    #[test]
+    fn follow_dir_link_visits_a_target_once() {
+        let dir = TempDir::new();
+        dir.write("real/x.lock", "");
+        let first = link(&dir, "one", &dir.path().join("real"));
+        let second = link(&dir, "two", &dir.path().join("real"));
+        let skip = SkipRules::new(&[]);
+        let opts = walk_options(dir.path(), &skip);
+        assert!(follow_dir_link(&first, dir.path(), &opts));
+        assert!(!follow_dir_link(&second, dir.path(), &opts));
+    }
+
+    #[test]
+    fn follow_dir_link_stays_inside_the_project() {
+        let dir = TempDir::new();
+        let outside = TempDir::new();
+        let out = link(&dir, "out", outside.path());
+        let skip = SkipRules::new(&[]);
+        let opts = walk_options(dir.path(), &skip);
+        assert!(!follow_dir_link(&out, dir.path(), &opts));
+    }
+
+    #[test]
+    fn inspect_symlink_blocks_outside_and_dangling_targets() {
+        let dir = TempDir::new();
+        let outside = TempDir::new();
+        let opts = DiscoverSection::default();
+        dir.write("real.lock", "");
+        let inside = link(&dir, "inside.lock", &dir.path().join("real.lock"));
+        let out = link(&dir, "out.lock", &outside.write("x.lock", ""));
+        let dangling = link(&dir, "dangling.lock", &dir.path().join("missing"));
+
+        assert!(inspect_symlink(&dir.path().join("real.lock"), dir.path(), &opts).is_none());
+        let l = inspect_symlink(&inside, dir.path(), &opts).unwrap();
+        assert_eq!(l.target, Some(dir.path().join("real.lock")));
+        assert_eq!(l.blocked, None);
+        let l = inspect_symlink(&out, dir.path(), &opts).unwrap();
+        assert_eq!(l.blocked, Some("symlink target is outside the project"));
+        let l = inspect_symlink(&dangling, dir.path(), &opts).unwrap();
+        assert_eq!(l.target, None);
+        assert_eq!(l.blocked, Some("dangling symlink"));
+
+        let opts = DiscoverSection {
+            protect_link_targets: false,
+            ..DiscoverSection::default()
+        };
+        let l = inspect_symlink(&inside, dir.path(), &opts).unwrap();
+        assert!(l.blocked.unwrap().contains("protect_link_targets"));
+    }
+
+    #[test]
+    fn set_protected_refuses_blocked_links() {
+        let dir = TempDir::new();
+        let outside = TempDir::new();
+        dir.write("real.lock", "");
+        link(&dir, "inside.lock", &dir.path().join("real.lock"));
+        link(&dir, "out.lock", &outside.write("x.lock", ""));
+        let files = discover_files(
+            dir.path(),
+            &[Pattern::new("*.lock").unwrap()],
+            &DiscoverSection::default(),
+        );
+
+        let mut plan = Plan::default();
+        for f in &files {
+            let result = plan.set_protected(f, true);
+            let name = f.path.file_name().unwrap().to_string_lossy();
+            assert_eq!(result.is_err(), name == "out.lock", "{name}");
+        }
+        let outside_mode = file_mode(&outside.path().join("x.lock"));
+        assert!(plan.modes.iter().all(|m| !m.path.ends_with("out.lock")));
+        plan.apply(dir.path());
+        assert_eq!(file_mode(&outside.path().join("x.lock")), outside_mode);
+        assert!(is_file_readonly(&dir.path().join("real.lock")));
+    }
+
+    // -------------------------------------------------------------------------
+    // Merges
+    // -------------------------------------------------------------------------
+
+    const LOCK_TOML: &str = "[protect]\npatterns = [\"Cargo.lock\"]\n";
+
+    /// `main` changes Cargo.lock after `feature` branches off; `feature` is checked out.
+    fn merge_fixture() -> TempDir {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write(".donttouch.toml", LOCK_TOML);
+        dir.write("Cargo.lock", "v1\n");
+        dir.git(&["add", "."]);
+        dir.git(&["commit", "-qm", "init"]);
+        dir.git(&["branch", "feature"]);
+        dir.write("Cargo.lock", "v2\n");
+        dir.git(&["commit", "-qam", "bump"]);
+        dir.git(&["checkout", "-q", "feature"]);
+        dir.write("src.rs", "fn main() {}\n");
+        dir.git(&["add", "."]);
+        dir.git(&["commit", "-qm", "work"]);
+        dir
+    }
+
+    fn git_context(root: &Path) -> Context {
+        Context::detect(root, false)
+    }
+
+    #[test]
+    fn merge_ignores_protected_changes_from_one_parent() {
+        let dir = merge_fixture();
+        dir.git(&["merge", "-q", "--no-ff", "--no-commit", "main"]);
+        assert!(get_staged_changes(dir.path()).is_empty());
+        let state = do_check_hook(
+            "pre-merge-commit",
+            &[],
+            &config(LOCK_TOML),
+            dir.path(),
+            &git_context(dir.path()),
+        );
+        assert!(
+            matches!(state, State::Done { .. }),
+            "{}",
+            state_message(&state)
+        );
+
+        dir.git(&["commit", "-qm", "merge", "--no-verify"]);
+        assert!(commit_changes(dir.path(), "HEAD").is_empty());
+    }
+
+    #[test]
+    fn merge_flags_protected_changes_made_while_merging() {
+        let dir = merge_fixture();
+        dir.git(&["merge", "-q", "--no-ff", "--no-commit", "main"]);
+        dir.write("Cargo.lock", "v3\n");
+        dir.git(&["add", "Cargo.lock"]);
+        let state = do_check_hook(
+            "pre-merge-commit",
+            &[],
+            &config(LOCK_TOML),
+            dir.path(),
+            &git_context(dir.path()),
+        );
+        assert!(matches!(state, State::Error { .. }));
+
+        dir.git(&["commit", "-qm", "merge", "--no-verify"]);
+        let changed: Vec<String> = commit_changes(dir.path(), "HEAD")
+            .into_iter()
+            .map(|c| c.path)
+            .collect();
+        assert_eq!(changed, ["Cargo.lock"]);
+    }
+
+    fn pre_rebase(dir: &TempDir, toml: &str) -> State {
+        let args = ["feature".to_string(), "main".to_string()];
+        do_check_hook(
+            "pre-rebase",
+            &args,
+            &config(toml),
+            dir.path(),
+            &git_context(dir.path()),
+        )
+    }
+
+    #[test]
+    fn pre_rebase_warns_without_a_trailer() {
+        let dir = merge_fixture();
+        let state = pre_rebase(&dir, LOCK_TOML);
+        let message = state_message(&state);
+        assert!(matches!(state, State::Done { .. }), "{message}");
+        assert!(message.contains("Cargo.lock"), "{message}");
+        assert!(message.contains("Rebasing anyway"), "{message}");
+    }
+
+    #[test]
+    fn pre_rebase_blocks_unapproved_commits_with_a_trailer() {
+        let dir = merge_fixture();
+        let toml = format!("{LOCK_TOML}[approval]\ntrailer = \"Approved-by\"\n");
+        let state = pre_rebase(&dir, &toml);
+        let message = state_message(&state);
+        assert!(matches!(state, State::Error { .. }), "{message}");
+        assert!(message.contains("--no-verify"), "{message}");
+    }
+
+    #[test]
+    fn pre_rebase_uses_the_current_patterns() {
+        let dir = merge_fixture();
+        let toml = "[protect]\npatterns = [\"*.secret\"]\n[approval]\ntrailer = \"Approved-by\"\n";
+        let state = pre_rebase(&dir, toml);
+        assert!(
+            matches!(state, State::Done { .. }),
+            "{}",
+            state_message(&state)
+        );
+    }
+
+    #[test]
+    fn changed_from_every_parent_keeps_shared_paths() {
+        let change = |kind, path: &str| Change {
+            kind,
+            from: None,
+            path: path.into(),
+        };
+        let ours = vec![change('M', "a"), change('M', "b")];
+        let theirs = vec![change('M', "b"), change('A', "c")];
+        let kept: Vec<String> = changed_from_every_parent(vec![ours, theirs])
+            .into_iter()
+            .map(|c| c.path)
+            .collect();
+        assert_eq!(kept, ["b"]);
+        assert!(changed_from_every_parent(Vec::new()).is_empty());
+    }
+
+    // -------------------------------------------------------------------------
+    // Hook layouts
+    // -------------------------------------------------------------------------
+
+    /// Install into the hooks dir `root` resolves to, expect it at `expected`, then remove.
+    fn hooks_round_trip(root: &Path, expected: &Path) {
+        let policy = HookPolicy::default();
+        let hooks_dir = resolve_hooks_dir(root, false);
+        assert!(hooks_state(root, HookManager::GitHooks, &hooks_dir, policy) == HookState::Missing);
+
+        install_hooks(root, HookManager::GitHooks, &hooks_dir, false, policy).unwrap();
+        assert_eq!(std::fs::canonicalize(&hooks_dir).unwrap(), expected);
+        assert!(expected.join("pre-commit").is_file());
+        assert!(hooks_state(root, HookManager::GitHooks, &hooks_dir, policy) == HookState::Current);
+        assert!(matches!(
+            Context::detect(root, false),
+            Context::Git {
+                hooks_installed: true,
+                ..
+            }
+        ));
+
+        let mut plan = Plan::default();
+        plan_remove_hooks(&mut plan, root, HookManager::GitHooks, &hooks_dir);
+        assert!(plan.apply(root).ok());
+        assert!(hooks_state(root, HookManager::GitHooks, &hooks_dir, policy) == HookState::Missing);
+    }
+
+    /// A repository at `rel` with one commit.
+    fn repo(dir: &TempDir, rel: &str) -> PathBuf {
+        let path = dir.write(&format!("{rel}/README.md"), "# repo\n");
+        let root = path.parent().unwrap().to_path_buf();
+        let git = |args: &[&str]| {
+            let mut full = vec!["-C", root.to_str().unwrap()];
+            full.extend_from_slice(args);
+            dir.git(&full);
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-qm", "init"]);
+        root
+    }
+
+    #[test]
+    fn hooks_in_a_normal_clone() {
+        let dir = TempDir::new();
+        let root = repo(&dir, "clone");
+        hooks_round_trip(&root, &root.join(".git/hooks"));
+    }
+
+    #[test]
+    fn hooks_in_a_worktree() {
+        let dir = TempDir::new();
+        let main = repo(&dir, "main");
+        dir.git(&["-C", "main", "worktree", "add", "-q", "../wt"]);
+        let wt = dir.path().join("wt");
+        assert!(wt.join(".git").is_file());
+        hooks_round_trip(&wt, &main.join(".git/hooks"));
+    }
+
+    #[test]
+    fn hooks_in_a_submodule() {
+        let dir = TempDir::new();
+        repo(&dir, "lib");
+        let app = repo(&dir, "app");
+        dir.git(&["-C", "app", "submodule", "add", "-q", "../lib", "lib"]);
+        let sub = app.join("lib");
+        assert!(sub.join(".git").is_file());
+        hooks_round_trip(&sub, &app.join(".git/modules/lib/hooks"));
+    }
+
+    #[test]
+    fn hooks_with_core_hooks_path() {
+        let dir = TempDir::new();
+        let root = repo(&dir, "configured");
+        dir.git(&["-C", "configured", "config", "core.hooksPath", ".githooks"]);
+        std::fs::create_dir(root.join(".githooks")).unwrap();
+        hooks_round_trip(&root, &root.join(".githooks"));
+        assert!(!root.join(".git/hooks/pre-commit").exists());
+    }
+
+    fn binary() -> String {
+        installed_binary().unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn only_private_hook_scripts_embed_the_binary() {
+        let policy = HookPolicy::default();
+        let (hook_name, cmd) = HOOKS[0];
+        assert!(hook_block(cmd, hook_name, policy, true).contains(&binary()));
+        assert!(!hook_block(cmd, hook_name, policy, false).contains(&binary()));
+        assert!(!hook_one_liner(cmd, hook_name, policy).contains(&binary()));
+    }
+
+    #[test]
+    fn husky_hooks_do_not_embed_the_binary() {
+        let dir = TempDir::new();
+        let root = repo(&dir, "app");
+        std::fs::create_dir(root.join(".husky")).unwrap();
+        let hooks_dir = resolve_hooks_dir(&root, true);
+        install_hooks(
+            &root,
+            HookManager::Husky,
+            &hooks_dir,
+            false,
+            HookPolicy::default(),
+        )
+        .unwrap();
+        let script = std::fs::read_to_string(hooks_dir.join("pre-commit")).unwrap();
+        assert!(script.contains("donttouch"));
+        assert!(!script.contains(&binary()));
+    }
+
+    #[test]
+    fn unwritable_hook_fails_install_and_status() {
+        let dir = TempDir::new();
+        let root = repo(&dir, "app");
+        let context = git_context(&root);
+        let Context::Git { hooks_dir, .. } = &context else {
+            panic!("expected a git context");
+        };
+        std::fs::create_dir_all(hooks_dir.join("pre-push")).unwrap();
+        let config = config("[protect]\npatterns = []\n");
+
+        let installed = do_hooks(&HooksAction::Install, &config, &root, &context);
+        assert!(matches!(installed, State::Error { .. }));
+        assert!(state_message(&installed).contains("1 of"));
+        assert!(hooks_dir.join("pre-commit").is_file());
+
+        let status = do_hooks(&HooksAction::Status, &config, &root, &context);
+        assert!(matches!(status, State::Error { .. }));
+        assert!(state_message(&status).contains("not installed"));
+
+        std::fs::remove_dir(hooks_dir.join("pre-push")).unwrap();
+        let installed = do_hooks(&HooksAction::Install, &config, &root, &context);
+        assert!(matches!(installed, State::Done { .. }));
+        let status = do_hooks(&HooksAction::Status, &config, &root, &context);
+        assert!(matches!(status, State::Done { .. }));
+    }
+
+    #[test]
+    fn simple_git_hooks_commands_do_not_embed_the_binary() {
+        let dir = TempDir::new();
+        let root = repo(&dir, "app");
+        std::fs::write(
+            root.join("package.json"),
+            "{\n  \"simple-git-hooks\": {}\n}\n",
+        )
+        .unwrap();
+        let policy = HookPolicy::default();
+        install_hooks(&root, HookManager::SimpleGitHooks, &root, false, policy).unwrap();
+        let json = std::fs::read_to_string(root.join("package.json")).unwrap();
+        assert!(json.contains("donttouch"));
+        assert!(!json.contains(&binary()));
+        assert!(manager_state(&root, HookManager::SimpleGitHooks, policy) == HookState::Current);
+    }
+
+    // -------------------------------------------------------------------------
+    // Push and CI
+    // -------------------------------------------------------------------------
+
+    fn rev(dir: &TempDir, rev: &str) -> String {
+        dir.git(&["rev-parse", rev]).trim().to_string()
+    }
+
+    /// On a branch off `main`: commit 1 empties `patterns`, commit 2 changes Cargo.lock.
+    fn relaxed_fixture() -> TempDir {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write(".donttouch.toml", LOCK_TOML);
+        dir.write("Cargo.lock", "v1\n");
+        dir.git(&["add", "."]);
+        dir.git(&["commit", "-qm", "init"]);
+        dir.git(&["checkout", "-qb", "feature"]);
+        dir.write(".donttouch.toml", "[protect]\npatterns = []\n");
+        dir.git(&["commit", "-qam", "relax"]);
+        dir.write("Cargo.lock", "v2\n");
+        dir.git(&["commit", "-qam", "bump"]);
+        dir
+    }
+
+    #[test]
+    fn push_range_skips_commits_on_other_remote_branches() {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write("a", "");
+        dir.git(&["add", "."]);
+        dir.git(&["commit", "-qm", "a"]);
+        let tip = rev(&dir, "HEAD");
+        dir.git(&["commit", "-q", "--allow-empty", "-m", "b"]);
+        dir.git(&["update-ref", "refs/remotes/origin/other", "HEAD"]);
+        dir.git(&["commit", "-q", "--allow-empty", "-m", "c"]);
+        let local = rev(&dir, "HEAD");
+
+        let mut args = vec!["rev-list".to_string()];
+        args.extend(push_range(dir.path(), &local, &tip));
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        assert_eq!(dir.git(&args).trim(), local);
+
+        let zero = "0".repeat(40);
+        assert!(push_range(dir.path(), &zero, &tip).is_empty());
+    }
+
+    #[test]
+    fn first_push_to_a_new_remote_skips_commits_on_other_remotes() {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write(".donttouch.toml", LOCK_TOML);
+        dir.write("Cargo.lock", "v1\n");
+        dir.git(&["add", "."]);
+        dir.git(&["commit", "-qm", "init"]);
+        dir.write("Cargo.lock", "v2\n");
+        dir.git(&["commit", "-qam", "bump"]);
+        dir.git(&["update-ref", "refs/remotes/origin/main", "HEAD"]);
+        dir.write("src.rs", "");
+        dir.git(&["add", "."]);
+        dir.git(&["commit", "-qm", "work"]);
+
+        // Pushing to "fork", which has no remote-tracking refs yet
+        let zero = "0".repeat(40);
+        let range = push_range(dir.path(), &rev(&dir, "HEAD"), &zero);
+        let mut args = vec!["rev-list"];
+        args.extend(range.iter().map(String::as_str));
+        assert_eq!(dir.git(&args).trim(), rev(&dir, "HEAD"));
+        let patterns = compile_patterns(&[String::from("Cargo.lock")]);
+        assert!(protected_commits(dir.path(), &args[1..], &patterns).is_empty());
+    }
+
+    #[test]
+    fn protected_commits_reads_merges_from_one_log() {
+        let dir = merge_fixture();
+        dir.git(&["merge", "-q", "--no-ff", "-m", "merge", "main"]);
+        let patterns = compile_patterns(&[String::from("Cargo.lock")]);
+        let flagged = protected_commits(dir.path(), &["HEAD^1..HEAD"], &patterns);
+        let shas: Vec<Option<String>> = flagged.into_iter().map(|(sha, _)| sha).collect();
+        assert_eq!(shas, [Some(rev(&dir, "main"))]);
+    }
+
+    #[test]
+    fn ci_keeps_patterns_an_earlier_commit_removed() {
+        let dir = relaxed_fixture();
+        let working = config("[protect]\npatterns = []\n");
+        let state = do_ci(
+            true,
+            "main",
+            "HEAD",
+            &working,
+            dir.path(),
+            &git_context(dir.path()),
+        );
+        let message = state_message(&state);
+        assert!(matches!(state, State::Error { .. }), "{message}");
+        assert!(message.contains("Cargo.lock"), "{message}");
+    }
+
+    #[test]
+    fn ci_reads_rules_from_the_base_config() {
+        let dir = merge_fixture();
+        dir.git(&["checkout", "-q", "main"]);
+        dir.git(&["checkout", "-qb", "bump"]);
+        dir.write("Cargo.lock", "v3\n");
+        dir.git(&["commit", "-qam", "bump again"]);
+        let working = config("[protect]\npatterns = []\n[approval]\ntrailer = \"Approved-by\"\n");
+        let state = do_ci(
+            true,
+            "feature",
+            "HEAD",
+            &working,
+            dir.path(),
+            &git_context(dir.path()),
+        );
+        let message = state_message(&state);
+        assert!(matches!(state, State::Error { .. }), "{message}");
+        assert!(
+            message.contains("Configure [approval] trailer"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn signers_match_full_fingerprints_and_long_key_ids() {
+        let gpg = "1E1A 2B3C 4D5E 6F70 8192 A3B4 4AEE 18F8 3AFD EB23";
+        assert!(signer_matches(
+            gpg,
+            "1E1A2B3C4D5E6F708192A3B44AEE18F83AFDEB23"
+        ));
+        assert!(signer_matches("4aee18f83afdeb23", gpg));
+        assert!(!signer_matches("3AFDEB23", gpg));
+        assert!(!signer_matches("23", gpg));
+        assert!(!signer_matches("", gpg));
+
+        let ssh = "SHA256:3vB8kQ2xYzAbCdEfGhIjKlMnOpQrStUvWxYz0123456";
+        assert!(signer_matches(ssh, ssh));
+        assert!(!signer_matches(&ssh.to_lowercase(), ssh));
+        assert!(!signer_matches(&ssh.to_uppercase(), ssh));
+        assert!(!signer_matches("SHA256:3vB8", ssh));
+    }
+
+    #[test]
+    fn short_signers_are_rejected_when_the_config_loads() {
+        let approval = |signers: &str| {
+            config(&format!(
+                "[protect]\npatterns = []\n[approval]\ntrailer = \"Approved-by\"\nsigners = [{signers}]\n"
+            ))
+            .approval
+        };
+        assert!(approval(r#""4AEE18F83AFDEB23", "SHA256:3vB8kQ2x""#)
+            .validate()
+            .is_ok());
+        let error = approval(r#""3AFDEB23""#).validate().unwrap_err();
+        assert!(error.contains("3AFDEB23"), "{error}");
+        assert!(approval(r#""""#).validate().is_err());
+        assert!(approval(r#""not-a-key-at-all!""#).validate().is_err());
+    }
+
+    #[test]
+    fn new_branch_push_keeps_patterns_an_earlier_commit_removed() {
+        let dir = relaxed_fixture();
+        let zero = "0".repeat(40);
+        let range = push_range(dir.path(), &rev(&dir, "HEAD"), &zero);
+        let args: Vec<&str> = range.iter().map(String::as_str).collect();
+        let flagged = protected_commits(dir.path(), &args, &[]);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].0, Some(rev(&dir, "HEAD")));
+    }
+
+    // -------------------------------------------------------------------------
+    // Nested agent files
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn nested_agent_files_list_only_their_own_patterns() {
+        let toml = "[protect]\npatterns = [\"pkg/secret.txt\", \"other/*.lock\", \"*.pem\"]\n\
+                    [discover]\ngitignore = true\ncache = true\n";
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write(".donttouch.toml", toml);
+        dir.write(".gitignore", "build/\n");
+        for rel in [
+            "CLAUDE.md",
+            "pkg/CLAUDE.md",
+            "build/CLAUDE.md",
+            "node_modules/dep/CLAUDE.md",
+        ] {
+            dir.write(rel, "# Notes\n");
+        }
+        let config = config(toml);
+        let nested = find_nested_agent_files(dir.path(), &["CLAUDE.md"], &config.discover);
+        assert_eq!(nested, ["pkg/CLAUDE.md"]);
+
+        inject_agent_instructions(dir.path(), &config, false, false).unwrap();
+        let read = |rel| std::fs::read_to_string(dir.path().join(rel)).unwrap();
+        let pkg = read("pkg/CLAUDE.md");
+        assert!(pkg.contains("`pkg/secret.txt`"), "{pkg}");
+        assert!(pkg.contains("`*.pem`"), "{pkg}");
+        assert!(!pkg.contains("other/"), "{pkg}");
+        assert!(read("CLAUDE.md").contains("`other/*.lock`"));
+        assert_eq!(read("build/CLAUDE.md"), "# Notes\n");
+        assert_eq!(read("node_modules/dep/CLAUDE.md"), "# Notes\n");
+
+        // A new nested file is found even with a cached listing
+        dir.write("other/CLAUDE.md", "# Notes\n");
+        let nested = find_nested_agent_files(dir.path(), &["CLAUDE.md"], &config.discover);
+        assert_eq!(nested, ["other/CLAUDE.md", "pkg/CLAUDE.md"]);
+    }
+
+    // -------------------------------------------------------------------------
+    // Structured agent files
+    // -------------------------------------------------------------------------
+
+    const TARGETS_TOML: &str = "[protect]\npatterns = [\"Cargo.lock\"]\n\
+        [[inject.targets]]\nformat = \"yaml\"\npath = \"agent.yaml\"\nkey = \"rules\"\ncreate = true\n\
+        [[inject.targets]]\nformat = \"json\"\npath = \"agent.json\"\nkey = \"rules\"\ncreate = true\n";
+
+    fn instructions() -> String {
+        render_instructions(&config(TARGETS_TOML), None)
+    }
+
+    #[test]
+    fn yaml_instructions_join_an_existing_list() {
+        let original = "rules:\n    - keep me\nmodel: x\n";
+        let block = instructions();
+        let added = yaml_instructions(original, "rules", Some(&block)).unwrap();
+        assert!(added.starts_with(&format!("rules:\n    {BLOCK_BEGIN}\n    - |\n")));
+        assert!(added.contains("    - keep me\nmodel: x\n"), "{added}");
+        assert_eq!(
+            yaml_instructions(&added, "rules", Some(&block)).unwrap(),
+            added
+        );
+        assert_eq!(yaml_instructions(&added, "rules", None).unwrap(), original);
+    }
+
+    #[test]
+    fn yaml_instructions_add_a_missing_list() {
+        let block = instructions();
+        let added = yaml_instructions("model: x\n", "rules", Some(&block)).unwrap();
+        assert!(added.starts_with(&format!("model: x\n\nrules:\n  {BLOCK_BEGIN}\n")));
+        let added_to_empty = yaml_instructions("rules: []\n", "rules", Some(&block)).unwrap();
+        assert!(added_to_empty.starts_with("rules:\n  "), "{added_to_empty}");
+
+        assert_eq!(
+            yaml_instructions(&added, "rules", None).unwrap(),
+            "model: x\n\nrules: []\n"
+        );
+        assert_eq!(
+            yaml_instructions(&added_to_empty, "rules", None).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn json_instructions_round_trip() {
+        let block = instructions();
+        let original = "{\n  \"rules\": [\n    \"keep me\"\n  ],\n  \"model\": \"x\"\n}\n";
+        let added = json_instructions(original, "rules", Some(&block)).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&added).unwrap();
+        assert_eq!(json["rules"][0], "keep me");
+        assert_eq!(json["rules"][1], block.as_str());
+        assert_eq!(
+            json_instructions(&added, "rules", Some(&block)).unwrap(),
+            added
+        );
+        assert_eq!(json_instructions(&added, "rules", None).unwrap(), original);
+
+        let added = json_instructions("{\"model\": \"x\"}", "rules", Some(&block)).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&added).unwrap();
+        assert_eq!(json["rules"], serde_json::json!([block]));
+        let removed = json_instructions(&added, "rules", None).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&removed).unwrap(),
+            serde_json::json!({"model": "x", "rules": []})
+        );
+    }
+
+    #[test]
+    fn malformed_structured_files_are_reported() {
+        let block = instructions();
+        let yaml = yaml_instructions("rules: keep me\n", "rules", Some(&block));
+        assert!(yaml.unwrap_err().contains("not a block list"));
+        let json = |content| json_instructions(content, "rules", Some(&block)).unwrap_err();
+        assert!(json("{\"rules\": ").contains("invalid JSON"));
+        assert!(json("[]").contains("not a JSON object"));
+        assert!(json("{\"rules\": \"keep me\"}").contains("not an array"));
+
+        let dir = TempDir::new();
+        dir.write(".donttouch.toml", TARGETS_TOML);
+        dir.write("agent.json", "{\"rules\": ");
+        let error =
+            inject_agent_instructions(dir.path(), &config(TARGETS_TOML), false, false).unwrap_err();
+        assert!(error.contains("agent.json"), "{error}");
+        assert!(!dir.path().join("agent.yaml").exists());
+    }
+
+    #[test]
+    fn update_and_remove_created_structured_files() {
+        let dir = TempDir::new();
+        dir.write(".donttouch.toml", TARGETS_TOML);
+        let config = config(TARGETS_TOML);
+        let yaml = dir.path().join("agent.yaml");
+        let json = dir.path().join("agent.json");
+
+        inject_agent_instructions(dir.path(), &config, false, false).unwrap();
+        let written = (
+            std::fs::read_to_string(&yaml).unwrap(),
+            std::fs::read_to_string(&json).unwrap(),
+        );
+        let updated = inject_agent_instructions(dir.path(), &config, true, false).unwrap();
+        assert!(
+            updated.contains("agent.yaml (already up to date)"),
+            "{updated}"
+        );
+        assert!(
+            updated.contains("agent.json (already up to date)"),
+            "{updated}"
+        );
+        assert_eq!(std::fs::read_to_string(&yaml).unwrap(), written.0);
+        assert_eq!(std::fs::read_to_string(&json).unwrap(), written.1);
+
+        let mut plan = Plan::default();
+        plan_remove_agent_instructions(&mut plan, dir.path(), &config);
+        assert!(plan.apply(dir.path()).ok());
+        assert!(!yaml.exists());
+        assert!(!json.exists());
+    }
+
+    // -------------------------------------------------------------------------
+    // Check
+    // -------------------------------------------------------------------------
+
+    const ENFORCE_TOML: &str = "[protect]\npatterns = [\"Cargo.lock\"]\n[inject]\nenforce = true\n";
+
+    /// Discover and lock the protected files, then run `check`.
+    fn locked_check(dir: &TempDir) -> State {
+        let config = config(ENFORCE_TOML);
+        let files = discover_files(
+            dir.path(),
+            &compile_patterns(&config.protected_patterns(dir.path())),
+            &config.discover,
+        );
+        let mut plan = Plan::default();
+        for f in &files {
+            plan.set_protected(f, true).unwrap();
+        }
+        assert!(plan.apply(dir.path()).ok());
+        let files = discover_files(
+            dir.path(),
+            &compile_patterns(&config.protected_patterns(dir.path())),
+            &config.discover,
+        );
+        do_check(&files, &config, dir.path(), &Context::Plain)
+    }
+
+    #[test]
+    fn staged_changes_wait_for_commit_msg_only_when_it_is_installed() {
+        let dir = merge_fixture();
+        let toml = format!("{LOCK_TOML}[approval]\ntrailer = \"Approved-by\"\n");
+        dir.write(".donttouch.toml", &toml);
+        dir.write("Cargo.lock", "v3\n");
+        dir.git(&["add", "Cargo.lock"]);
+        let config = config(&toml);
+
+        let state = do_check(&[], &config, dir.path(), &git_context(dir.path()));
+        let message = state_message(&state);
+        assert!(matches!(state, State::Error { .. }), "{message}");
+        assert!(
+            message.contains("commit-msg hook isn't installed"),
+            "{message}"
+        );
+
+        let context = git_context(dir.path());
+        let Context::Git { hooks_dir, .. } = &context else {
+            panic!("expected a git context");
+        };
+        let policy = HookPolicy::default();
+        install_hooks(dir.path(), HookManager::GitHooks, hooks_dir, false, policy).unwrap();
+        let state = do_check(&[], &config, dir.path(), &git_context(dir.path()));
+        let message = state_message(&state);
+        assert!(matches!(state, State::Done { .. }), "{message}");
+        assert!(message.contains("checked at commit-msg"), "{message}");
+    }
+
+    #[test]
+    fn enforce_ignores_agent_files_never_injected() {
+        let dir = TempDir::new();
+        dir.write("Cargo.lock", "");
+        dir.write(".donttouch.toml", ENFORCE_TOML);
+        dir.write("CONVENTIONS.md", "# Conventions\n");
+        dir.write("CLAUDE.md", "# Project\n");
+        let state = locked_check(&dir);
+        assert!(
+            matches!(state, State::Done { .. }),
+            "{}",
+            state_message(&state)
+        );
+    }
+
+    #[test]
+    fn enforce_flags_edited_instructions() {
+        let dir = TempDir::new();
+        dir.write("Cargo.lock", "");
+        dir.write(".donttouch.toml", ENFORCE_TOML);
+        dir.write("CLAUDE.md", "# Project\n");
+        dir.write("CONVENTIONS.md", "# Conventions\n");
+        inject_agent_instructions(dir.path(), &config(ENFORCE_TOML), false, false).unwrap();
+        assert!(matches!(locked_check(&dir), State::Done { .. }));
+
+        let claude = dir.path().join("CLAUDE.md");
+        let edited = std::fs::read_to_string(&claude)
+            .unwrap()
+            .replace("Cargo.lock", "nothing");
+        std::fs::write(&claude, edited).unwrap();
+        let state = locked_check(&dir);
+        let message = state_message(&state);
+        assert!(matches!(state, State::Error { .. }), "{message}");
+        assert!(
+            message.contains("CLAUDE.md (instructions are outdated)"),
+            "{message}"
+        );
+        assert!(!message.contains("CONVENTIONS.md"), "{message}");
+    }
+
+    // -------------------------------------------------------------------------
+    // Plans
+    // -------------------------------------------------------------------------
+
+    /// Bytes and mode of every file in `dir`, by root-relative path.
+    fn snapshot(dir: &TempDir) -> Vec<(String, Vec<u8>, Option<u32>)> {
+        let mut files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .flatten()
+            .filter(|e| e.path().is_file())
+            .map(|e| {
+                let path = e.path();
+                let name = e.file_name().to_string_lossy().into_owned();
+                (name, std::fs::read(&path).unwrap(), file_mode(&path))
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Unlock, rewrite, create, delete and lock files, with `fail` as the last step.
+    fn failing_plan(dir: &TempDir, fail: impl FnOnce(&mut Plan)) -> Plan {
+        let locked = dir.write("a.lock", "v1\n");
+        set_file_mode(&locked, 0o444).unwrap();
+        let doomed = dir.path().join("gone.bin");
+        std::fs::write(&doomed, [0xff, 0x00, 0xfe]).unwrap();
+        set_file_mode(&doomed, 0o640).unwrap();
+        let open = dir.write("b.lock", "v1\n");
+
+        let mut plan = Plan::default();
+        plan.set_mode(&locked, 0o644);
+        plan.write(locked.clone(), "v2\n".into());
+        plan.write(dir.path().join("new.txt"), "new\n".into());
+        plan.delete(doomed);
+        plan.set_readonly(&open, true);
+        plan.set_mode(&locked, 0o444);
+        fail(&mut plan);
+        plan
+    }
+
+    #[test]
+    fn failed_apply_rolls_back_every_earlier_step() {
+        // The last lock fails: the file was never created
+        let dir = TempDir::new();
+        let plan = failing_plan(&dir, |plan| {
+            plan.set_mode(&dir.path().join("missing.txt"), 0o444)
+        });
+        let before = snapshot(&dir);
+        let applied = plan.apply(dir.path());
+        assert!(!applied.ok());
+        assert!(applied.error.as_deref().unwrap().contains("missing.txt"));
+        assert!(applied.rollback_errors.is_empty());
+        assert_eq!(snapshot(&dir), before);
+
+        // A write fails before any lock ran: its directory can't be created
+        let dir = TempDir::new();
+        dir.write("blocker", "");
+        let plan = failing_plan(&dir, |plan| {
+            let write = FileChange {
+                path: dir.path().join("blocker/x.txt"),
+                before: None,
+                after: Some("x\n".into()),
+            };
+            plan.files.push(write);
+        });
+        let before = snapshot(&dir);
+        let applied = plan.apply(dir.path());
+        assert!(applied.error.as_deref().unwrap().contains("blocker"));
+        assert!(applied.rollback_errors.is_empty());
+        assert_eq!(snapshot(&dir), before);
+        assert!(applied.render(&[]).contains("Rolled back"));
+    }
+
+    // -------------------------------------------------------------------------
+    // Backups
+    // -------------------------------------------------------------------------
+
+    fn backup_of(root: &Path, path: &Path) -> Backup {
+        Backup {
+            version: BACKUP_VERSION,
+            created: 0,
+            root: root.display().to_string(),
+            files: vec![BackedUpFile {
+                path: path.display().to_string(),
+                mode: Some("0755".into()),
+                content: Some("#!/bin/sh\n".into()),
+            }],
+        }
+    }
+
+    #[test]
+    fn restore_accepts_hooks_outside_the_worktree() {
+        let dir = TempDir::new();
+        let main = repo(&dir, "main");
+        dir.git(&["-C", "main", "worktree", "add", "-q", "../wt"]);
+        let wt = dir.path().join("wt");
+        let hook = main.join(".git/hooks/pre-commit");
+        let plan = backup_of(&wt, &hook).plan(&wt).unwrap();
+        assert_eq!(plan.files[0].path, hook);
+    }
+
+    #[test]
+    fn restore_rejects_paths_outside_the_project() {
+        let dir = TempDir::new();
+        let root = repo(&dir, "app");
+        let elsewhere = TempDir::new();
+        for path in [
+            elsewhere.path().join("pre-commit"),
+            PathBuf::from("../pre-commit"),
+            root.join(".git/../../pre-commit"),
+        ] {
+            let err = backup_of(&root, &path).plan(&root).err();
+            assert!(
+                err.is_some_and(|e| e.contains("leaves the project")),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // Lock / unlock matrix
+    // -------------------------------------------------------------------------
+
+    fn protection_fixture(enabled: bool) -> TempDir {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        dir.write("Cargo.lock", "");
+        dir.write(
+            ".donttouch.toml",
+            &format!("[protect]\nenabled = {enabled}\npatterns = [\"Cargo.lock\"]\n"),
+        );
+        dir
+    }
+
+    /// Dispatch `cmd` the way `handle_start` does once the config is read.
+    fn run(dir: &TempDir, cmd: Command) -> State {
+        let root = dir.path().to_path_buf();
+        let cmd = resolve_deprecated(cmd);
+        let config = read_config(dir);
+        let context = Context::detect(&root, false);
+        let files = discover_files(
+            &root,
+            &compile_patterns(&config.protected_patterns(&root)),
+            &config.discover,
+        );
+        if config.protect.enabled {
+            dispatch_enabled(cmd, config, files, root, context)
+        } else {
+            dispatch_disabled(cmd, config, files, root, context)
+        }
+    }
+
+    fn read_config(dir: &TempDir) -> ConfigFile {
+        config(&std::fs::read_to_string(dir.path().join(".donttouch.toml")).unwrap())
+    }
+
+    fn assert_locked(dir: &TempDir, locked: bool) {
+        assert_eq!(read_config(dir).protect.enabled, locked);
+        assert_eq!(is_file_readonly(&dir.path().join("Cargo.lock")), locked);
+        assert_eq!(
+            is_file_readonly(&dir.path().join(".donttouch.toml")),
+            locked
+        );
+    }
+
+    fn target(dir: &TempDir) -> String {
+        dir.path().display().to_string()
+    }
+
+    #[test]
+    fn lock_enables_from_either_state() {
+        for enabled in [false, true] {
+            let dir = protection_fixture(enabled);
+            let state = run(&dir, Command::Lock { dry_run: false });
+            assert!(
+                matches!(state, State::Done { .. }),
+                "{}",
+                state_message(&state)
+            );
+            assert_locked(&dir, true);
+        }
+    }
+
+    #[test]
+    fn unlock_disables_from_either_state() {
+        for enabled in [false, true] {
+            let dir = protection_fixture(enabled);
+            run(&dir, Command::Lock { dry_run: false });
+            if !enabled {
+                run(
+                    &dir,
+                    Command::Unlock {
+                        target: target(&dir),
+                        dry_run: false,
+                    },
+                );
+            }
+            let state = run(
+                &dir,
+                Command::Unlock {
+                    target: target(&dir),
+                    dry_run: false,
+                },
+            );
+            assert!(
+                matches!(state, State::Done { .. }),
+                "{}",
+                state_message(&state)
+            );
+            assert_locked(&dir, false);
+        }
+    }
+
+    #[test]
+    fn deprecated_aliases_lock_and_unlock() {
+        let dir = protection_fixture(false);
+        run(&dir, Command::Enable { dry_run: false });
+        assert_locked(&dir, true);
+        run(
+            &dir,
+            Command::Disable {
+                target: target(&dir),
+                dry_run: false,
+            },
+        );
+        assert_locked(&dir, false);
+    }
+
+    #[test]
+    fn lock_reports_paths_relative_to_the_root() {
+        let dir = protection_fixture(false);
+        let state = run(&dir, Command::Lock { dry_run: false });
+        let message = state_message(&state);
+        assert!(message.contains("🔒 Cargo.lock\n"), "{message}");
+        assert!(!message.contains(&target(&dir)), "{message}");
+    }
+
+    #[test]
+    fn dry_runs_change_nothing() {
+        let dir = protection_fixture(false);
+        run(&dir, Command::Lock { dry_run: true });
+        assert_locked(&dir, false);
+        run(&dir, Command::Lock { dry_run: false });
+        run(
+            &dir,
+            Command::Unlock {
+                target: target(&dir),
+                dry_run: true,
+            },
+        );
+        assert_locked(&dir, true);
+    }
+
+    #[test]
+    fn check_depends_on_the_state() {
+        let dir = protection_fixture(false);
+        let state = run(&dir, Command::Check);
+        assert!(state_message(&state).contains("Skipping check"));
+
+        let dir = protection_fixture(true);
+        assert!(matches!(run(&dir, Command::Check), State::Error { .. }));
+        run(&dir, Command::Lock { dry_run: false });
+        assert!(matches!(run(&dir, Command::Check), State::Done { .. }));
+    }
+
+    #[test]
+    fn check_push_blocks_while_disabled() {
+        let check_push = || Command::CheckPush {
+            remote: None,
+            url: None,
+        };
+        let dir = protection_fixture(false);
+        let state = run(&dir, check_push());
+        assert!(
+            matches!(&state, State::Error { message } if message.contains("push blocked")),
+            "{}",
+            state_message(&state)
+        );
+
+        run(&dir, Command::Lock { dry_run: false });
+        let state = run(&dir, check_push());
+        assert!(
+            matches!(&state, State::Done { message } if message.contains("Push allowed")),
+            "{}",
+            state_message(&state)
+        );
+    }
+
+    // -------------------------------------------------------------------------
+    // Process lock
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn lock_file_stays_out_of_plain_projects() {
+        let dir = TempDir::new();
+        let path = lock_path(dir.path());
+        assert!(!path.starts_with(dir.path()));
+        assert_eq!(path, lock_path(&dir.path().join(".")));
+        assert_ne!(path, lock_path(TempDir::new().path()));
+
+        let repo = TempDir::new();
+        repo.git(&["init", "-q"]);
+        assert!(lock_path(repo.path()).starts_with(repo.path().join(".git")));
+    }
+
+    #[test]
+    fn shared_lock_waits_for_a_mutating_run() {
+        let dir = TempDir::new();
+        let path = lock_path(dir.path());
+        assert!(share_process_lock(dir.path()).unwrap().is_none());
+
+        let held = std::fs::File::create(&path).unwrap();
+        held.lock().unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            drop(held);
+        });
+        let start = Instant::now();
+        assert!(share_process_lock(dir.path()).unwrap().is_some());
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        release.join().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Run `cmd` while another donttouch holds the lock, rewriting the config
+    /// to `LOCK_TOML` before it lets go.
+    fn run_during_lock(dir: &TempDir, cmd: Command) -> State {
+        dir.write(".donttouch.toml", "[protect]\npatterns = []\n");
+        let held = std::fs::File::create(lock_path(dir.path())).unwrap();
+        held.lock().unwrap();
+        let config = dir.path().join(".donttouch.toml");
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::fs::write(config, LOCK_TOML).unwrap();
+            drop(held);
+        });
+        let start = Instant::now();
+        let state = run_configured(cmd, dir.path().to_path_buf(), false);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        writer.join().unwrap();
+        state
+    }
+
+    #[test]
+    fn read_only_commands_wait_for_a_running_lock() {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"]);
+        let why = Command::Why {
+            file: "Cargo.lock".into(),
+        };
+        assert!(!why.mutates());
+        let state = run_during_lock(&dir, why);
+        let message = state_message(&state);
+        assert!(message.contains("Cargo.lock is protected by"), "{message}");
+
+        for cmd in [
+            Command::Doctor,
+            Command::Hooks {
+                action: HooksAction::Status,
+            },
+            Command::Ci {
+                base: "HEAD".into(),
+                head: "HEAD".into(),
+            },
+        ] {
+            assert!(!cmd.mutates());
+            run_during_lock(&dir, cmd);
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // Helpers
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn parse_name_status_reads_renames_and_copies() {
+        let changes = parse_name_status(b"M\0a\0R100\0old\0new\0C75\0src\0dst\0D\0gone\0");
+        let summary: Vec<(char, Option<&str>, &str)> = changes
+            .iter()
+            .map(|c| (c.kind, c.from.as_deref(), c.path.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ('M', None, "a"),
+                ('R', Some("old"), "new"),
+                ('C', Some("src"), "dst"),
+                ('D', None, "gone"),
+            ]
+        );
+        assert!(parse_name_status(b"").is_empty());
+        // A truncated record is dropped rather than misread
+        assert_eq!(parse_name_status(b"M\0a\0R100\0old\0").len(), 1);
+    }
+
+    #[test]
+    fn gitignore_path_translates_patterns() {
+        assert_eq!(gitignore_path("*.lock"), Ok(("*.lock".into(), None)));
+        assert_eq!(
+            gitignore_path("Cargo.lock"),
+            Ok(("/Cargo.lock".into(), None))
+        );
+        assert_eq!(gitignore_path("./a/b.txt"), Ok(("/a/b.txt".into(), None)));
+        assert_eq!(
+            gitignore_path("src/*.rs"),
+            Ok(("/src/**/*.rs".into(), None))
+        );
+        assert_eq!(gitignore_path("**/*.pem"), Ok(("/**/*.pem".into(), None)));
+        assert_eq!(gitignore_path("my file"), Ok(("/my\\ file".into(), None)));
+        assert!(gitignore_path("a*/b").unwrap().1.is_some());
+        assert!(gitignore_path("*.a*b").unwrap().1.is_some());
+        assert!(gitignore_path("!keep").is_err());
+        assert!(gitignore_path("#x").is_err());
+    }
+
+    #[test]
+    fn lefthook_add_and_strip_round_trip() {
+        let policy = HookPolicy::default();
+        let original = "pre-commit:\n  commands:\n    lint:\n      run: cargo clippy\n";
+        let added = lefthook_add(original, policy).unwrap();
+        assert!(added.contains(BLOCK_BEGIN));
+        assert!(added.contains("lint:"));
+        assert_eq!(lefthook_add(&added, policy), None);
+        assert_eq!(strip_yaml_block(&added).as_deref(), Some(original));
+        assert_eq!(strip_yaml_block(original), None);
+    }
+
+    #[test]
+    fn rewrite_marked_blocks_replaces_and_removes() {
+        let (hook_name, cmd) = HOOKS[0];
+        let block = hook_block(cmd, hook_name, HookPolicy::default(), false);
+        let script = format!("#!/bin/sh\necho before\n\n{block}\necho after\n");
+
+        let removed = rewrite_marked_blocks(&script, None).unwrap();
+        assert!(!removed.contains(BLOCK_BEGIN));
+        assert!(removed.contains("echo before") && removed.contains("echo after"));
+
+        let replaced = rewrite_marked_blocks(&script, Some("# new")).unwrap();
+        assert_eq!(replaced.matches("# new").count(), 1);
+        assert!(!replaced.contains(BLOCK_BEGIN));
+        assert!(replaced.find("# new") < replaced.find("echo after"));
+
+        assert_eq!(rewrite_marked_blocks("#!/bin/sh\necho hi\n", None), None);
+    }
+
+    #[test]
+    fn unified_diff_formats_hunks() {
+        assert_eq!(
+            unified_diff("a\nb\nc\n", "a\nB\nc\n", "a/x", "b/x"),
+            "--- a/x\n+++ b/x\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+        assert_eq!(
+            unified_diff("", "new\n", "x", "y"),
+            "--- x\n+++ y\n@@ -0,0 +1,1 @@\n+new\n"
+        );
+        assert_eq!(unified_diff("same\n", "same\n", "x", "y"), "--- x\n+++ y\n");
+
+        // Changes more than twice the context apart get their own hunks
+        let old: String = (1..=20).map(|i| format!("{i}\n")).collect();
+        let new: String = (1..=20)
+            .map(|i| match i {
+                2 | 19 => format!("changed {i}\n"),
+                _ => format!("{i}\n"),
+            })
+            .collect();
+        assert_eq!(
+            unified_diff(&old, &new, "x", "y").matches("@@ -").count(),
+            2
+        );
+    }
+
+    #[test]
+    fn parse_selection_accepts_lists_and_keywords() {
+        assert_eq!(parse_selection("", 3), Some(vec![0, 1, 2]));
+        assert_eq!(parse_selection("ALL", 2), Some(vec![0, 1]));
+        assert_eq!(parse_selection("none", 3), Some(vec![]));
+        assert_eq!(parse_selection("3, 1 3", 3), Some(vec![2, 0]));
+        assert_eq!(parse_selection("0", 3), None);
+        assert_eq!(parse_selection("4", 3), None);
+        assert_eq!(parse_selection("x", 3), None);
+    }
+
+    #[test]
+    fn utc_stamp_formats_dates() {
+        assert_eq!(utc_stamp(0), "19700101-000000");
+        assert_eq!(utc_stamp(951_782_400), "20000229-000000");
+        assert_eq!(utc_stamp(1_700_000_000), "20231114-221320");
+        assert_eq!(utc_stamp(4_102_444_799), "20991231-235959");
+    }
+
+    // -------------------------------------------------------------------------
+    // Doctor
+    // -------------------------------------------------------------------------
+
+    #[test]
+    fn doctor_exits_non_zero_on_warnings() {
+        let dir = TempDir::new();
+        dir.write("Cargo.lock", "");
+        let toml = "[protect]\npatterns = [\"Cargo.lock\", \"nothing-matches-*.x\"]\n";
+        dir.write(".donttouch.toml", toml);
+        let state = do_doctor(&config(toml), dir.path(), &Context::Plain);
+        assert!(
+            matches!(&state, State::Warning { message } if message.contains("nothing-matches-*.x"))
+        );
+    }
+
+    #[test]
+    fn doctor_exits_non_zero_on_errors() {
+        let dir = TempDir::new();
+        let toml = "[protect]\npatterns = [\"[unclosed\"]\n";
+        dir.write(".donttouch.toml", toml);
+        let state = do_doctor(&config(toml), dir.path(), &Context::Plain);
+        assert!(matches!(state, State::Error { .. }));
+    }
+
+    #[test]
+    fn doctor_passes_a_clean_config() {
+        let dir = TempDir::new();
+        dir.write("Cargo.lock", "");
+        let toml = "[protect]\npatterns = [\"Cargo.lock\"]\n";
+        dir.write(".donttouch.toml", toml);
+        dir.write("CLAUDE.md", "# Project\n");
+        inject_agent_instructions(dir.path(), &config(toml), false, false).unwrap();
+        let state = do_doctor(&config(toml), dir.path(), &Context::Plain);
+        assert!(
+            matches!(state, State::Done { .. }),
+            "{}",
+            state_message(&state)
+        );
+    }
+
+    fn state_message(state: &State) -> &str {
+        match state {
+            State::Done { message } | State::Error { message } | State::Warning { message } => {
+                message
+            }
+            _ => "",
+        }
+    }
 }